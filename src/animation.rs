@@ -0,0 +1,708 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use gltf::{
+    animation::{util::ReadOutputs, Interpolation},
+    buffer, Document,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::error::warn;
+
+/// Refers to an animation clip either by its position in the document or by its name.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClipRef {
+    Index(usize),
+    Name(String),
+}
+
+impl ClipRef {
+    /// Numbers are treated as indices, everything else as a clip name.
+    pub fn parse(value: &str) -> Self {
+        match value.parse::<usize>() {
+            Ok(index) => ClipRef::Index(index),
+            Err(_) => ClipRef::Name(value.to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for ClipRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClipRef::Index(index) => write!(f, "#{}", index),
+            ClipRef::Name(name) => write!(f, "{:?}", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LoopMode {
+    /// Play the range once, the last frame lands exactly on the end time.
+    #[default]
+    Once,
+    /// The end time is left out so the last frame flows back into the first.
+    Loop,
+    /// Play forward and then backward, without repeating the turning frames.
+    PingPong,
+}
+
+impl LoopMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "once" => Some(LoopMode::Once),
+            "loop" => Some(LoopMode::Loop),
+            "pingpong" | "ping-pong" => Some(LoopMode::PingPong),
+            _ => None,
+        }
+    }
+}
+
+/// Which clip to export and how to sample it.
+#[derive(Debug, Clone)]
+pub struct ClipSettings {
+    /// `None` picks the first clip in the document.
+    pub clip: Option<ClipRef>,
+    /// Start of the range in seconds, defaults to the start of the clip.
+    pub start: Option<f32>,
+    /// End of the range in seconds, defaults to the end of the clip.
+    pub end: Option<f32>,
+    pub fps: f32,
+    pub loop_mode: LoopMode,
+}
+
+impl Default for ClipSettings {
+    fn default() -> Self {
+        Self {
+            clip: None,
+            start: None,
+            end: None,
+            fps: 24.0,
+            loop_mode: LoopMode::Once,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ClipInfo {
+    pub index: usize,
+    pub name: Option<String>,
+    pub start: f32,
+    pub end: f32,
+    /// Names of the nodes targeted by at least one channel, in document order.
+    pub joints: Vec<String>,
+}
+
+impl ClipInfo {
+    pub fn duration(&self) -> f32 {
+        self.end - self.start
+    }
+}
+
+pub fn clip_info(animation: &gltf::Animation, buffers: &[buffer::Data]) -> ClipInfo {
+    let (start, end) = clip_time_range(animation, buffers);
+
+    let mut joints: Vec<(usize, String)> = vec![];
+    for channel in animation.channels() {
+        let node = channel.target().node();
+        if joints.iter().any(|(index, _)| *index == node.index()) {
+            continue;
+        }
+        let name = node
+            .name()
+            .map(|name| name.to_string())
+            .unwrap_or_else(|| format!("node_{}", node.index()));
+        joints.push((node.index(), name));
+    }
+    joints.sort_by_key(|(index, _)| *index);
+
+    ClipInfo {
+        index: animation.index(),
+        name: animation.name().map(|name| name.to_string()),
+        start,
+        end,
+        joints: joints.into_iter().map(|(_, name)| name).collect(),
+    }
+}
+
+pub fn clip_infos(document: &Document, buffers: &[buffer::Data]) -> Vec<ClipInfo> {
+    document
+        .animations()
+        .map(|animation| clip_info(&animation, buffers))
+        .collect()
+}
+
+pub fn print_clips(document: &Document, buffers: &[buffer::Data]) {
+    let clips = clip_infos(document, buffers);
+    if clips.is_empty() {
+        println!("No animation clips");
+        return;
+    }
+
+    for clip in clips {
+        println!(
+            "[{}] {} - {:.3}s ({:.3}s..{:.3}s)",
+            clip.index,
+            clip.name.as_deref().unwrap_or("<unnamed>"),
+            clip.duration(),
+            clip.start,
+            clip.end,
+        );
+        println!("    joints: {}", clip.joints.join(", "));
+    }
+}
+
+pub fn select_clip<'a>(
+    document: &'a Document,
+    clip: Option<&ClipRef>,
+) -> Option<gltf::Animation<'a>> {
+    match clip {
+        None => document.animations().next(),
+        Some(ClipRef::Index(index)) => document.animations().nth(*index),
+        Some(ClipRef::Name(name)) => document
+            .animations()
+            .find(|animation| animation.name() == Some(name.as_str())),
+    }
+}
+
+/// The earliest and latest keyframe time over all channels of the clip.
+pub fn clip_time_range(animation: &gltf::Animation, buffers: &[buffer::Data]) -> (f32, f32) {
+    let mut start = f32::INFINITY;
+    let mut end = f32::NEG_INFINITY;
+
+    for channel in animation.channels() {
+        let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+        let Some(inputs) = reader.read_inputs() else {
+            continue;
+        };
+        for time in inputs {
+            start = start.min(time);
+            end = end.max(time);
+        }
+    }
+
+    if start > end {
+        return (0.0, 0.0);
+    }
+
+    (start, end)
+}
+
+impl ClipSettings {
+    /// Clamps the requested start/end to the clip and returns the trimmed range.
+    pub fn trimmed_range(&self, clip_start: f32, clip_end: f32) -> (f32, f32) {
+        let start = self.start.unwrap_or(clip_start).clamp(clip_start, clip_end);
+        let end = self.end.unwrap_or(clip_end).clamp(start, clip_end);
+        (start, end)
+    }
+
+    /// Clip-local times of every exported frame.
+    pub fn sample_times(&self, clip_start: f32, clip_end: f32) -> Vec<f32> {
+        let (start, end) = self.trimmed_range(clip_start, clip_end);
+        let step = 1.0 / self.fps;
+
+        // every frame strictly before `end`, the small bias keeps float noise from
+        // producing a frame right next to it
+        let frame_count = ((end - start) / step - 1e-2).ceil().max(1.0) as usize;
+        let mut forward = (0..frame_count)
+            .map(|frame| start + frame as f32 * step)
+            .collect::<Vec<_>>();
+
+        match self.loop_mode {
+            // the end pose equals the start pose when wrapped around, so it is left out
+            LoopMode::Loop => forward,
+            LoopMode::Once => {
+                if end > start {
+                    forward.push(end);
+                }
+                forward
+            }
+            LoopMode::PingPong => {
+                if end > start {
+                    forward.push(end);
+                }
+                let backward = forward
+                    .iter()
+                    .rev()
+                    .skip(1)
+                    .take(forward.len().saturating_sub(2))
+                    .copied()
+                    .collect::<Vec<_>>();
+                forward.extend(backward);
+                forward
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Property {
+    Translation,
+    Rotation,
+    Scale,
+}
+
+/// Keyframes of a single animated property, values are stored as `Vec4` so translations,
+/// rotations and scales can share the interpolation code.
+#[derive(Debug, Clone)]
+struct ChannelSampler {
+    property: Property,
+    interpolation: Interpolation,
+    times: Vec<f32>,
+    /// For cubic splines every keyframe has three entries: in-tangent, value, out-tangent.
+    values: Vec<Vec4>,
+}
+
+impl ChannelSampler {
+    fn value(&self, key: usize) -> Vec4 {
+        match self.interpolation {
+            Interpolation::CubicSpline => self.values[key * 3 + 1],
+            _ => self.values[key],
+        }
+    }
+
+    fn sample(&self, time: f32) -> Vec4 {
+        let last = self.times.len() - 1;
+        if self.times.len() == 1 || time <= self.times[0] {
+            return self.value(0);
+        }
+        if time >= self.times[last] {
+            return self.value(last);
+        }
+
+        // index of the keyframe at or before `time`
+        let key = self.times.partition_point(|t| *t <= time) - 1;
+        let delta = self.times[key + 1] - self.times[key];
+        let s = (time - self.times[key]) / delta;
+
+        match self.interpolation {
+            Interpolation::Step => self.value(key),
+            Interpolation::Linear => {
+                let (a, b) = (self.value(key), self.value(key + 1));
+                match self.property {
+                    Property::Rotation => Quat::from_vec4(a)
+                        .normalize()
+                        .slerp(Quat::from_vec4(b).normalize(), s)
+                        .into(),
+                    _ => a.lerp(b, s),
+                }
+            }
+            Interpolation::CubicSpline => {
+                // hermite spline as described in appendix C of the glTF spec
+                let v0 = self.values[key * 3 + 1];
+                let b0 = self.values[key * 3 + 2];
+                let a1 = self.values[(key + 1) * 3];
+                let v1 = self.values[(key + 1) * 3 + 1];
+
+                let s2 = s * s;
+                let s3 = s2 * s;
+                let value = (2.0 * s3 - 3.0 * s2 + 1.0) * v0
+                    + delta * (s3 - 2.0 * s2 + s) * b0
+                    + (-2.0 * s3 + 3.0 * s2) * v1
+                    + delta * (s3 - s2) * a1;
+
+                match self.property {
+                    Property::Rotation => value.normalize(),
+                    _ => value,
+                }
+            }
+        }
+    }
+}
+
+/// The animated properties of a single node, properties without a channel keep their rest value.
+#[derive(Debug, Clone, Default)]
+struct NodeChannels {
+    translation: Option<ChannelSampler>,
+    rotation: Option<ChannelSampler>,
+    scale: Option<ChannelSampler>,
+}
+
+/// Evaluates the channels of an animation clip to local node transforms at arbitrary times.
+#[derive(Debug, Clone, Default)]
+pub struct AnimationSampler {
+    nodes: HashMap<usize, NodeChannels>,
+}
+
+impl AnimationSampler {
+    pub fn new(animation: &gltf::Animation, buffers: &[buffer::Data]) -> Self {
+        let mut nodes: HashMap<usize, NodeChannels> = HashMap::new();
+
+        for channel in animation.channels() {
+            let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+            let (Some(inputs), Some(outputs)) = (reader.read_inputs(), reader.read_outputs())
+            else {
+                continue;
+            };
+
+            let (property, values) = match outputs {
+                ReadOutputs::Translations(translations) => (
+                    Property::Translation,
+                    translations
+                        .map(|t| Vec3::from(t).extend(0.0))
+                        .collect::<Vec<_>>(),
+                ),
+                ReadOutputs::Rotations(rotations) => (
+                    Property::Rotation,
+                    rotations.into_f32().map(Vec4::from).collect(),
+                ),
+                ReadOutputs::Scales(scales) => (
+                    Property::Scale,
+                    scales.map(|s| Vec3::from(s).extend(0.0)).collect(),
+                ),
+                // morph targets don't affect the skeleton
+                ReadOutputs::MorphTargetWeights(_) => continue,
+            };
+
+            let sampler = ChannelSampler {
+                property,
+                interpolation: channel.sampler().interpolation(),
+                times: inputs.collect(),
+                values,
+            };
+
+            let values_per_key = match sampler.interpolation {
+                Interpolation::CubicSpline => 3,
+                _ => 1,
+            };
+            if sampler.times.is_empty()
+                || sampler.values.len() < sampler.times.len() * values_per_key
+            {
+                warn(format!(
+                    "skipping malformed channel targeting node {}",
+                    channel.target().node().index()
+                ));
+                continue;
+            }
+
+            let node = nodes.entry(channel.target().node().index()).or_default();
+            match property {
+                Property::Translation => node.translation = Some(sampler),
+                Property::Rotation => node.rotation = Some(sampler),
+                Property::Scale => node.scale = Some(sampler),
+            }
+        }
+
+        Self { nodes }
+    }
+
+    /// Local transform of the node at `time`, properties that aren't animated are taken from `rest`.
+    pub fn sample(&self, node_index: usize, rest: Transform, time: f32) -> Transform {
+        let Some(channels) = self.nodes.get(&node_index) else {
+            return rest;
+        };
+
+        let mut transform = rest;
+        if let Some(translation) = &channels.translation {
+            transform.translation = translation.sample(time).truncate();
+        }
+        if let Some(rotation) = &channels.rotation {
+            transform.rotation = Quat::from_vec4(rotation.sample(time)).normalize();
+        }
+        if let Some(scale) = &channels.scale {
+            transform.scale = scale.sample(time).truncate();
+        }
+
+        transform
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel(
+        property: Property,
+        interpolation: Interpolation,
+        times: &[f32],
+        values: &[Vec4],
+    ) -> ChannelSampler {
+        ChannelSampler {
+            property,
+            interpolation,
+            times: times.to_vec(),
+            values: values.to_vec(),
+        }
+    }
+
+    fn assert_near(a: Vec4, b: Vec4) {
+        assert!(a.abs_diff_eq(b, 1e-5), "{} != {}", a, b);
+    }
+
+    fn load(path: &str) -> (Document, Vec<buffer::Data>) {
+        let (document, buffers, _) = gltf::import(path).unwrap();
+        (document, buffers)
+    }
+
+    /// Keyframe times and values of a channel, read straight from the accessors.
+    fn keyframes(
+        channel: &gltf::animation::Channel,
+        buffers: &[buffer::Data],
+    ) -> (Vec<f32>, Vec<Vec4>) {
+        let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+        let times = reader.read_inputs().unwrap().collect();
+        let values = match reader.read_outputs().unwrap() {
+            ReadOutputs::Translations(values) | ReadOutputs::Scales(values) => {
+                values.map(|v| Vec3::from(v).extend(0.0)).collect()
+            }
+            ReadOutputs::Rotations(values) => values.into_f32().map(Vec4::from).collect(),
+            ReadOutputs::MorphTargetWeights(_) => unreachable!(),
+        };
+        (times, values)
+    }
+
+    fn sampled(sampler: &AnimationSampler, node: usize, property: &str, time: f32) -> Vec4 {
+        let transform = sampler.sample(node, Transform::IDENTITY, time);
+        match property {
+            "translation" => transform.translation.extend(0.0),
+            "rotation" => Vec4::from(transform.rotation),
+            _ => transform.scale.extend(0.0),
+        }
+    }
+
+    /// Every channel of the sample hits its keyframes, holds them outside the clip and blends
+    /// linearly in between.
+    fn check_linear_sample(path: &str) {
+        let (document, buffers) = load(path);
+        let animation = document.animations().next().unwrap();
+        let sampler = AnimationSampler::new(&animation, &buffers);
+
+        for channel in animation.channels() {
+            assert_eq!(channel.sampler().interpolation(), Interpolation::Linear);
+            let node = channel.target().node().index();
+            let property = match channel.target().property() {
+                gltf::animation::Property::Translation => "translation",
+                gltf::animation::Property::Rotation => "rotation",
+                gltf::animation::Property::Scale => "scale",
+                gltf::animation::Property::MorphTargetWeights => continue,
+            };
+            let (times, values) = keyframes(&channel, &buffers);
+            let same = |a: Vec4, b: Vec4| {
+                // q and -q are the same rotation
+                if property == "rotation" && a.dot(b) < 0.0 {
+                    assert_near(a, -b)
+                } else {
+                    assert_near(a, b)
+                }
+            };
+
+            for (time, value) in times.iter().zip(&values) {
+                same(sampled(&sampler, node, property, *time), *value);
+            }
+            same(sampled(&sampler, node, property, times[0] - 1.0), values[0]);
+            same(
+                sampled(&sampler, node, property, times[times.len() - 1] + 1.0),
+                values[values.len() - 1],
+            );
+
+            let middle = (times[0] + times[1]) / 2.0;
+            let expected = match property {
+                "rotation" => Quat::from_vec4(values[0])
+                    .normalize()
+                    .slerp(Quat::from_vec4(values[1]).normalize(), 0.5)
+                    .into(),
+                _ => values[0].lerp(values[1], 0.5),
+            };
+            same(sampled(&sampler, node, property, middle), expected);
+        }
+    }
+
+    #[test]
+    fn samples_box_animated() {
+        check_linear_sample("input/BoxAnimated.gltf");
+    }
+
+    #[test]
+    fn samples_rigged_simple() {
+        check_linear_sample("input/RiggedSimple.gltf");
+    }
+
+    #[test]
+    fn unanimated_nodes_keep_their_rest() {
+        let (document, buffers) = load("input/BoxAnimated.gltf");
+        let sampler = AnimationSampler::new(&document.animations().next().unwrap(), &buffers);
+        let rest = Transform::from_xyz(1.0, 2.0, 3.0);
+        assert_eq!(sampler.sample(1, rest, 1.0), rest);
+    }
+
+    #[test]
+    fn step_holds_until_the_next_keyframe() {
+        let sampler = channel(
+            Property::Translation,
+            Interpolation::Step,
+            &[1.0, 2.0, 3.0],
+            &[Vec4::ZERO, Vec4::ONE, Vec4::splat(2.0)],
+        );
+        assert_near(sampler.sample(0.0), Vec4::ZERO);
+        assert_near(sampler.sample(1.99), Vec4::ZERO);
+        assert_near(sampler.sample(2.0), Vec4::ONE);
+        assert_near(sampler.sample(2.5), Vec4::ONE);
+        assert_near(sampler.sample(9.0), Vec4::splat(2.0));
+    }
+
+    #[test]
+    fn linear_blends_and_clamps() {
+        let sampler = channel(
+            Property::Translation,
+            Interpolation::Linear,
+            &[1.0, 3.0],
+            &[Vec4::ZERO, Vec4::new(2.0, 4.0, 6.0, 0.0)],
+        );
+        assert_near(sampler.sample(2.0), Vec4::new(1.0, 2.0, 3.0, 0.0));
+        assert_near(sampler.sample(1.5), Vec4::new(0.5, 1.0, 1.5, 0.0));
+        assert_near(sampler.sample(-5.0), Vec4::ZERO);
+        assert_near(sampler.sample(5.0), Vec4::new(2.0, 4.0, 6.0, 0.0));
+    }
+
+    #[test]
+    fn linear_rotations_take_the_shortest_path() {
+        // the same quarter turn written with a negative w, the long way round is 270 degrees
+        let quarter = -Quat::from_rotation_z(std::f32::consts::FRAC_PI_2);
+        let sampler = channel(
+            Property::Rotation,
+            Interpolation::Linear,
+            &[0.0, 1.0],
+            &[Quat::IDENTITY.into(), quarter.into()],
+        );
+        let halfway = Quat::from_vec4(sampler.sample(0.5));
+        let expected = Quat::from_rotation_z(std::f32::consts::FRAC_PI_4);
+        assert!(halfway.angle_between(expected) < 1e-4, "{}", halfway);
+    }
+
+    #[test]
+    fn cubic_spline_follows_the_tangents() {
+        // a single segment from 0 to 1 over two seconds, with tangents of a straight line the
+        // spline is that line, with flat ones it eases in and out
+        let times = [0.0, 2.0];
+        let straight = channel(
+            Property::Translation,
+            Interpolation::CubicSpline,
+            &times,
+            &[
+                Vec4::splat(0.5),
+                Vec4::ZERO,
+                Vec4::splat(0.5),
+                Vec4::splat(0.5),
+                Vec4::ONE,
+                Vec4::splat(0.5),
+            ],
+        );
+        assert_near(straight.sample(0.5), Vec4::splat(0.25));
+        assert_near(straight.sample(1.0), Vec4::splat(0.5));
+
+        let flat = channel(
+            Property::Translation,
+            Interpolation::CubicSpline,
+            &times,
+            &[
+                Vec4::ZERO,
+                Vec4::ZERO,
+                Vec4::ZERO,
+                Vec4::ZERO,
+                Vec4::ONE,
+                Vec4::ZERO,
+            ],
+        );
+        // 3s^2 - 2s^3 at s = 1/4
+        assert_near(flat.sample(0.5), Vec4::splat(0.15625));
+        assert_near(flat.sample(1.0), Vec4::splat(0.5));
+        assert_near(flat.sample(-1.0), Vec4::ZERO);
+        assert_near(flat.sample(3.0), Vec4::ONE);
+    }
+
+    #[test]
+    fn cubic_spline_rotations_stay_unit_length() {
+        let half = Quat::from_rotation_y(std::f32::consts::FRAC_PI_2);
+        let sampler = channel(
+            Property::Rotation,
+            Interpolation::CubicSpline,
+            &[0.0, 1.0],
+            &[
+                Vec4::ZERO,
+                Quat::IDENTITY.into(),
+                Vec4::ZERO,
+                Vec4::ZERO,
+                half.into(),
+                Vec4::ZERO,
+            ],
+        );
+        for time in [0.1, 0.5, 0.9] {
+            assert!((sampler.sample(time).length() - 1.0).abs() < 1e-5);
+        }
+        assert_near(sampler.sample(1.0), half.into());
+    }
+
+    fn clip(start: Option<f32>, end: Option<f32>, loop_mode: LoopMode) -> ClipSettings {
+        ClipSettings {
+            start,
+            end,
+            fps: 4.0,
+            loop_mode,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn trims_to_the_clip() {
+        let once = LoopMode::Once;
+        assert_eq!(clip(None, None, once).trimmed_range(0.5, 2.0), (0.5, 2.0));
+        assert_eq!(
+            clip(Some(1.0), Some(1.5), once).trimmed_range(0.5, 2.0),
+            (1.0, 1.5)
+        );
+        assert_eq!(
+            clip(Some(-1.0), Some(3.0), once).trimmed_range(0.5, 2.0),
+            (0.5, 2.0)
+        );
+        // an end before the start leaves an empty range at the start
+        assert_eq!(
+            clip(Some(1.5), Some(1.0), once).trimmed_range(0.5, 2.0),
+            (1.5, 1.5)
+        );
+        assert_eq!(
+            clip(Some(3.0), None, once).trimmed_range(0.5, 2.0),
+            (2.0, 2.0)
+        );
+    }
+
+    #[test]
+    fn samples_once_up_to_the_end() {
+        let times = clip(None, None, LoopMode::Once).sample_times(0.0, 1.0);
+        assert_eq!(times, [0.0, 0.25, 0.5, 0.75, 1.0]);
+
+        // the end lands between two frames
+        let times = clip(None, Some(0.6), LoopMode::Once).sample_times(0.0, 1.0);
+        assert_eq!(times, [0.0, 0.25, 0.5, 0.6]);
+    }
+
+    #[test]
+    fn samples_loops_without_the_end() {
+        let times = clip(None, None, LoopMode::Loop).sample_times(0.0, 1.0);
+        assert_eq!(times, [0.0, 0.25, 0.5, 0.75]);
+    }
+
+    #[test]
+    fn samples_ping_pong_without_repeating_the_turns() {
+        let times = clip(None, None, LoopMode::PingPong).sample_times(0.0, 1.0);
+        assert_eq!(times, [0.0, 0.25, 0.5, 0.75, 1.0, 0.75, 0.5, 0.25]);
+
+        let times = clip(Some(0.5), Some(0.75), LoopMode::PingPong).sample_times(0.0, 1.0);
+        assert_eq!(times, [0.5, 0.75]);
+    }
+
+    #[test]
+    fn samples_a_single_frame_of_an_empty_range() {
+        for loop_mode in [LoopMode::Once, LoopMode::Loop, LoopMode::PingPong] {
+            assert_eq!(clip(None, None, loop_mode).sample_times(0.0, 0.0), [0.0]);
+            assert_eq!(
+                clip(Some(0.5), Some(0.5), loop_mode).sample_times(0.0, 1.0),
+                [0.5]
+            );
+            // start after end
+            assert_eq!(
+                clip(Some(0.75), Some(0.25), loop_mode).sample_times(0.0, 1.0),
+                [0.75]
+            );
+        }
+    }
+}
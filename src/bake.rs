@@ -0,0 +1,76 @@
+use bevy::prelude::*;
+use gltf::image;
+
+use crate::{
+    skinning::SkinnedMesh,
+    vox_tree::VoxNode,
+    voxelize::{voxelize, VoxelSet},
+    VoxFrame, VoxScene,
+};
+
+/// Levels per color channel, the baked colors have to fit in the 255 usable palette entries.
+const COLOR_LEVELS: f32 = 6.0;
+
+/// Voxelizes the mesh once per pose and writes the results as a flip-book: a single shape with
+/// one model per frame, keyed by `_f`.
+///
+/// `poses` holds the skinned vertex positions of every frame, in voxel units. Every model gets
+/// the same size so the shape doesn't jump around between frames.
+pub fn write_baked(
+    scene: &mut VoxScene,
+    name: &str,
+    mesh: &SkinnedMesh,
+    poses: &[Vec<Vec3>],
+    images: &[image::Data],
+) -> VoxNode {
+    let frames = poses
+        .iter()
+        .map(|positions| voxelize(mesh, positions, images))
+        .collect::<Vec<VoxelSet>>();
+
+    let (min, max) = frames
+        .iter()
+        .flat_map(|voxels| voxels.keys())
+        .fold((IVec3::MAX, IVec3::MIN), |(min, max), position| {
+            (min.min(*position), max.max(*position))
+        });
+    if min.cmpgt(max).any() {
+        // nothing was voxelized, still write a frame so the node isn't dangling
+        let model = scene.voxel_model(UVec3::ONE, []);
+        return scene.animated_models(Some(name), VoxFrame::default(), &[model]);
+    }
+
+    let size = (max - min + IVec3::ONE).as_uvec3();
+
+    let models = frames
+        .iter()
+        .map(|voxels| {
+            // hash map order changes between runs, sorting keeps the palette and the file the same
+            let mut voxels = voxels.iter().collect::<Vec<_>>();
+            voxels.sort_by_key(|(position, _)| (position.z, position.y, position.x));
+            let voxels = voxels
+                .into_iter()
+                .map(|(position, color)| {
+                    (
+                        (*position - min).as_uvec3(),
+                        scene.color_index(quantize(*color)),
+                    )
+                })
+                .collect::<Vec<_>>();
+            scene.voxel_model(size, voxels)
+        })
+        .collect::<Vec<_>>();
+
+    let center = min + scene.model_pivot(size);
+    scene.animated_models(
+        Some(name),
+        VoxFrame::from_translation(center.as_vec3()),
+        &models,
+    )
+}
+
+fn quantize(color: [u8; 4]) -> [u8; 4] {
+    let step = 255.0 / (COLOR_LEVELS - 1.0);
+    let [r, g, b, _] = color.map(|channel| ((channel as f32 / step).round() * step) as u8);
+    [r, g, b, 255]
+}
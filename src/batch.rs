@@ -0,0 +1,252 @@
+use std::{
+    collections::HashMap,
+    panic::{self, AssertUnwindSafe},
+    path::{Component, Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use rayon::prelude::*;
+
+use crate::{
+    animation, convert_file,
+    error::{self, Error, Result},
+    frames, Options,
+};
+
+#[derive(Debug)]
+pub enum Outcome {
+    Converted,
+    /// The file has no skin, so there is nothing to convert.
+    Skipped,
+    Failed(Error),
+}
+
+#[derive(Debug)]
+pub struct BatchResult {
+    pub input: PathBuf,
+    pub outcome: Outcome,
+    pub warnings: Vec<String>,
+    pub duration: Duration,
+}
+
+/// Every `.gltf` and `.glb` file below a directory, or matching a glob pattern.
+///
+/// Also returns the directory the outputs are made relative to: the directory itself, or the
+/// part of the pattern before the first wildcard.
+pub fn find_inputs(pattern: &str) -> Result<(PathBuf, Vec<PathBuf>)> {
+    let (root, pattern) = if Path::new(pattern).is_dir() {
+        let root = PathBuf::from(pattern);
+        let pattern = root.join("**").join("*").to_string_lossy().into_owned();
+        (root, pattern)
+    } else {
+        let root = Path::new(pattern)
+            .components()
+            .take_while(|component| {
+                !matches!(component, Component::Normal(name)
+                    if name.to_string_lossy().contains(['*', '?', '[']))
+            })
+            .collect::<PathBuf>();
+        // a plain file name is its own root, outputs go straight into the output directory
+        let root = match root.is_dir() {
+            true => root,
+            false => root.parent().map(Path::to_path_buf).unwrap_or_default(),
+        };
+        (root, pattern.to_string())
+    };
+
+    let paths = glob::glob(&pattern)
+        .map_err(|error| Error::Unsupported(format!("pattern {:?}: {}", pattern, error)))?;
+
+    let mut inputs = vec![];
+    for path in paths {
+        let path = path.map_err(|error| Error::Io(error.path().to_path_buf(), error.into()))?;
+        let is_gltf = path.extension().is_some_and(|extension| {
+            extension.eq_ignore_ascii_case("gltf") || extension.eq_ignore_ascii_case("glb")
+        });
+        if is_gltf && path.is_file() {
+            inputs.push(path);
+        }
+    }
+    inputs.sort();
+
+    Ok((root, inputs))
+}
+
+/// Converts every input with the same options and its own settings from the config, writing to
+/// the same relative path below `output_dir`. Runs on `jobs` threads, or one per core.
+///
+/// Inputs that would write the same output, like `a.gltf` and `a.glb`, or the same per-frame
+/// files fail without being converted.
+pub fn run(
+    options: &Options,
+    root: &Path,
+    inputs: &[PathBuf],
+    output_dir: &Path,
+    jobs: Option<usize>,
+) -> Result<Vec<BatchResult>> {
+    let mut pool = rayon::ThreadPoolBuilder::new();
+    if let Some(jobs) = jobs {
+        pool = pool.num_threads(jobs);
+    }
+    let pool = pool
+        .build()
+        .map_err(|error| Error::Batch(format!("can't start the batch threads: {}", error)))?;
+
+    let outputs = inputs
+        .iter()
+        .map(|input| {
+            let relative = input.strip_prefix(root).unwrap_or(input);
+            output_dir.join(relative).with_extension("vox")
+        })
+        .collect::<Vec<_>>();
+    let written = inputs
+        .iter()
+        .zip(&outputs)
+        .map(|(input, output)| {
+            // an input that can't be read fails its conversion with the same error
+            written_paths(options, input, output).unwrap_or_else(|_| vec![output.clone()])
+        })
+        .collect::<Vec<_>>();
+    let mut writers = HashMap::<_, Vec<_>>::new();
+    for (input, paths) in inputs.iter().zip(&written) {
+        for path in paths {
+            writers.entry(path).or_default().push(input);
+        }
+    }
+
+    Ok(pool.install(|| {
+        inputs
+            .par_iter()
+            .zip(&outputs)
+            .zip(&written)
+            .map(|((input, output), paths)| {
+                let Some(path) = paths.iter().find(|path| writers[path].len() > 1) else {
+                    return convert_one(options, input, output.clone());
+                };
+                BatchResult {
+                    input: input.clone(),
+                    outcome: Outcome::Failed(Error::Batch(format!(
+                        "{} is written by {}",
+                        path.display(),
+                        writers[path]
+                            .iter()
+                            .map(|other| other.display().to_string())
+                            .collect::<Vec<_>>()
+                            .join(" and ")
+                    ))),
+                    warnings: vec![],
+                    duration: Duration::ZERO,
+                }
+            })
+            .collect()
+    }))
+}
+
+/// The paths the files written for `input` are named after: the output, and with `per_frame`
+/// the manifest, which shares its prefix with the frames.
+fn written_paths(options: &Options, input: &Path, output: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths = vec![output.to_path_buf()];
+    if options.per_frame {
+        let options = options.for_input(&input.to_string_lossy())?;
+        let clip_input = options.animation_from.as_deref().map_or(input, Path::new);
+        let document = gltf::Gltf::open(clip_input)?.document;
+        let clip = animation::select_clip(&document, options.clip.clip.as_ref());
+        let clip_name = clip.as_ref().and_then(|clip| clip.name());
+        let (directory, prefix) = frames::frame_prefix(output, clip_name);
+        paths.push(directory.join(format!("{}.json", prefix)));
+    }
+    Ok(paths)
+}
+
+fn convert_one(options: &Options, input: &Path, output: PathBuf) -> BatchResult {
+    let start = Instant::now();
+    // warnings of an earlier file on this thread
+    error::take_warnings();
+
+    let convert = || {
+        output
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .map_err(|error| Error::Io(output.clone(), error))?;
+        let mut options = options.for_input(&input.to_string_lossy())?;
+        options.output = output.to_string_lossy().into_owned();
+        convert_file(&options).map(|_| ())
+    };
+    // a panic only fails this file, not the whole batch
+    let result = panic::catch_unwind(AssertUnwindSafe(convert)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown error".to_string());
+        Err(Error::Batch(format!("panicked: {}", message)))
+    });
+
+    let outcome = match result {
+        Ok(()) => Outcome::Converted,
+        Err(Error::MissingSkin(None)) => Outcome::Skipped,
+        Err(error) => Outcome::Failed(error),
+    };
+
+    BatchResult {
+        input: input.to_path_buf(),
+        outcome,
+        warnings: error::take_warnings(),
+        duration: start.elapsed(),
+    }
+}
+
+pub fn print_summary(results: &[BatchResult]) {
+    let rows = results
+        .iter()
+        .map(|result| {
+            let (status, message) = match &result.outcome {
+                Outcome::Converted if result.warnings.is_empty() => ("ok", String::new()),
+                Outcome::Converted => ("warning", result.warnings.join("; ")),
+                Outcome::Skipped => ("skipped", "no skin".to_string()),
+                Outcome::Failed(error) => ("failed", error.to_string()),
+            };
+            [
+                status.to_string(),
+                result.warnings.len().to_string(),
+                format!("{:.2}s", result.duration.as_secs_f32()),
+                result.input.display().to_string(),
+                message,
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    let header = ["status", "warnings", "time", "input", "message"].map(str::to_string);
+    let mut widths = header.clone().map(|column| column.len());
+    for row in &rows {
+        for (width, column) in widths.iter_mut().zip(row) {
+            *width = (*width).max(column.len());
+        }
+    }
+
+    println!();
+    for row in std::iter::once(&header).chain(&rows) {
+        let line = format!(
+            "{:<w0$}  {:>w1$}  {:>w2$}  {:<w3$}  {}",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            row[4],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+            w3 = widths[3],
+        );
+        println!("{}", line.trim_end());
+    }
+
+    let count = |status: fn(&BatchResult) -> bool| results.iter().filter(|r| status(r)).count();
+    println!(
+        "\n{} converted ({} with warnings), {} skipped, {} failed",
+        count(|r| matches!(r.outcome, Outcome::Converted)),
+        count(|r| matches!(r.outcome, Outcome::Converted) && !r.warnings.is_empty()),
+        count(|r| matches!(r.outcome, Outcome::Skipped)),
+        count(|r| matches!(r.outcome, Outcome::Failed(_))),
+    );
+}
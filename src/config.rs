@@ -0,0 +1,351 @@
+use std::{collections::BTreeMap, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    animation::{ClipSettings, LoopMode},
+    coords::{Axis, CoordinateSystem, Handedness},
+    error::{Error, Result},
+    rig::RigSettings,
+    root_motion::RootMotion,
+};
+
+/// A color written as `#rrggbb` or `#rrggbbaa`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Color(pub [u8; 4]);
+
+impl TryFrom<String> for Color {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("expected a color like #ff8800, got {:?}", value);
+        let hex = value.strip_prefix('#').unwrap_or(&value);
+        if !matches!(hex.len(), 6 | 8) || !hex.is_ascii() {
+            return Err(invalid());
+        }
+
+        let mut color = [255; 4];
+        for (i, channel) in color.iter_mut().enumerate().take(hex.len() / 2) {
+            *channel = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| invalid())?;
+        }
+        Ok(Color(color))
+    }
+}
+
+impl From<Color> for String {
+    fn from(Color([r, g, b, a]): Color) -> Self {
+        match a {
+            255 => format!("#{:02x}{:02x}{:02x}", r, g, b),
+            _ => format!("#{:02x}{:02x}{:02x}{:02x}", r, g, b, a),
+        }
+    }
+}
+
+/// Settings of a single bone.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BoneOverride {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thickness: Option<f32>,
+    /// Filter the bone out or keep it, whatever `include` and `exclude` say.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exclude: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<Color>,
+}
+
+impl BoneOverride {
+    /// Takes every value that is set in `other`.
+    pub fn merge(&mut self, other: &BoneOverride) {
+        merge_option(&mut self.thickness, &other.thickness);
+        merge_option(&mut self.exclude, &other.exclude);
+        merge_option(&mut self.layer, &other.layer);
+        merge_option(&mut self.color, &other.color);
+    }
+}
+
+/// Everything about a conversion that can be set in a config file or on the command line.
+///
+/// Unset values are taken from whatever comes before, see `Config::settings_for`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    /// Name or index of the skin to convert, defaults to the first one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skin: Option<String>,
+    /// Name or index of the clip to export, defaults to the first one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clip: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fps: Option<f32>,
+    #[serde(rename = "loop", skip_serializing_if = "Option::is_none")]
+    pub loop_mode: Option<LoopMode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub root_motion: Option<RootMotion>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub root_bone: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bake: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scale: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thickness: Option<f32>,
+    /// Color of the bone parts.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<Color>,
+    /// Colors put at the start of the palette, in this order.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub palette: Option<Vec<Color>>,
+    /// Layer rules as `name=pattern,pattern`, like `--layer`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layers: Option<Vec<String>>,
+    /// Up axis of the source, see `CoordinateSystem::default` for the default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub up: Option<Axis>,
+    /// The way a character of the source faces, see `CoordinateSystem::default`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forward: Option<Axis>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub handedness: Option<Handedness>,
+    /// Only bones matching one of these become parts, see `filter::BonePattern`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include: Option<Vec<String>>,
+    /// Bones matching one of these don't become parts.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exclude: Option<Vec<String>>,
+    /// Merge filtered bones into the part of their parent instead of dropping them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub collapse: Option<bool>,
+    /// `left` or `right` to build that side of the character and mirror it onto the other, or
+    /// `none`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mirror: Option<String>,
+    /// `obj`, `gltf` or `glb` to also write the models as greedy meshed triangles next to the
+    /// output, or `none`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mesh: Option<String>,
+    /// Darken the corners of the exported meshes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ambient_occlusion: Option<bool>,
+    /// Write a `gltf` or `glb` mesh as one mesh skinned to the original skeleton, so the
+    /// animations of the input play on it, instead of a rigid mesh per part.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skinned_mesh: Option<bool>,
+    /// Also write the voxels as Qubicle Binary next to the output.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub qubicle: Option<bool>,
+    /// `mixamo`, `unreal` or `rigify` to name bones after the canonical humanoid skeleton, `auto`
+    /// to pick one of those, or `none`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bone_preset: Option<String>,
+    /// Canonical names of bones by their name in the rig, like `b_Hip_01 = "hips"`.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub bone_map: BTreeMap<String, String>,
+    /// A glTF to take the clip from, retargeted onto the input by the canonical bones.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub animation_from: Option<String>,
+    /// Like `bone_preset`, for the rig of `animation_from`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_bone_preset: Option<String>,
+    /// Like `bone_map`, for the rig of `animation_from`.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub source_bone_map: BTreeMap<String, String>,
+    /// Settings per bone, keyed by a `filter::BonePattern`.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub bones: BTreeMap<String, BoneOverride>,
+}
+
+impl Settings {
+    /// The built-in defaults, with every value that has one set.
+    pub fn defaults() -> Self {
+        let clip = ClipSettings::default();
+        let rig = RigSettings::default();
+        let coordinates = CoordinateSystem::default();
+        Self {
+            fps: Some(clip.fps),
+            loop_mode: Some(clip.loop_mode),
+            root_motion: Some(RootMotion::default()),
+            bake: Some(false),
+            scale: Some(rig.scale),
+            thickness: Some(rig.thickness),
+            color: Some(Color(rig.color)),
+            palette: Some(vec![]),
+            layers: Some(rig.layers.iter().map(|rule| rule.to_string()).collect()),
+            up: Some(coordinates.up),
+            forward: Some(coordinates.forward),
+            handedness: Some(coordinates.handedness),
+            include: Some(vec![]),
+            exclude: Some(vec![]),
+            collapse: Some(false),
+            mirror: Some("none".to_string()),
+            mesh: Some("none".to_string()),
+            ambient_occlusion: Some(false),
+            skinned_mesh: Some(false),
+            qubicle: Some(false),
+            bone_preset: Some("none".to_string()),
+            source_bone_preset: Some("auto".to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// Takes every value that is set in `other`, bones are merged per pattern.
+    pub fn merge(&mut self, other: &Settings) {
+        merge_option(&mut self.skin, &other.skin);
+        merge_option(&mut self.clip, &other.clip);
+        merge_option(&mut self.start, &other.start);
+        merge_option(&mut self.end, &other.end);
+        merge_option(&mut self.fps, &other.fps);
+        merge_option(&mut self.loop_mode, &other.loop_mode);
+        merge_option(&mut self.root_motion, &other.root_motion);
+        merge_option(&mut self.root_bone, &other.root_bone);
+        merge_option(&mut self.bake, &other.bake);
+        merge_option(&mut self.scale, &other.scale);
+        merge_option(&mut self.thickness, &other.thickness);
+        merge_option(&mut self.color, &other.color);
+        merge_option(&mut self.palette, &other.palette);
+        merge_option(&mut self.layers, &other.layers);
+        merge_option(&mut self.up, &other.up);
+        merge_option(&mut self.forward, &other.forward);
+        merge_option(&mut self.handedness, &other.handedness);
+        merge_option(&mut self.include, &other.include);
+        merge_option(&mut self.exclude, &other.exclude);
+        merge_option(&mut self.collapse, &other.collapse);
+        merge_option(&mut self.mirror, &other.mirror);
+        merge_option(&mut self.mesh, &other.mesh);
+        merge_option(&mut self.ambient_occlusion, &other.ambient_occlusion);
+        merge_option(&mut self.skinned_mesh, &other.skinned_mesh);
+        merge_option(&mut self.qubicle, &other.qubicle);
+        merge_option(&mut self.bone_preset, &other.bone_preset);
+        merge_option(&mut self.animation_from, &other.animation_from);
+        merge_option(&mut self.source_bone_preset, &other.source_bone_preset);
+        self.source_bone_map.extend(other.source_bone_map.clone());
+        self.bone_map.extend(other.bone_map.clone());
+        for (pattern, bone) in &other.bones {
+            self.bones.entry(pattern.clone()).or_default().merge(bone);
+        }
+    }
+}
+
+fn merge_option<T: Clone>(value: &mut Option<T>, other: &Option<T>) {
+    if other.is_some() {
+        value.clone_from(other);
+    }
+}
+
+/// Settings for the input files matching `pattern`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileSettings {
+    /// Glob on the input path, patterns without a `/` only look at the file name.
+    #[serde(rename = "match")]
+    pub pattern: String,
+    pub settings: Settings,
+}
+
+impl FileSettings {
+    pub fn matches(&self, input: &Path) -> Result<bool> {
+        let pattern = glob::Pattern::new(&self.pattern)
+            .map_err(|error| Error::Config(format!("match {:?}: {}", self.pattern, error)))?;
+        Ok(if self.pattern.contains('/') {
+            pattern.matches_path(input)
+        } else {
+            input
+                .file_name()
+                .is_some_and(|name| pattern.matches(&name.to_string_lossy()))
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Toml,
+    Ron,
+}
+
+impl ConfigFormat {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "toml" => Some(ConfigFormat::Toml),
+            "ron" => Some(ConfigFormat::Ron),
+            _ => None,
+        }
+    }
+
+    /// RON for `.ron` files, TOML for everything else.
+    pub fn of(path: &Path) -> Self {
+        match path.extension() {
+            Some(extension) if extension.eq_ignore_ascii_case("ron") => ConfigFormat::Ron,
+            _ => ConfigFormat::Toml,
+        }
+    }
+}
+
+/// A config file: default settings, followed by settings for specific input files.
+///
+/// ```toml
+/// [defaults]
+/// scale = 10.0
+///
+/// [defaults.bones."*_end"]
+/// exclude = true
+///
+/// [[files]]
+/// match = "Fox.gltf"
+/// settings.scale = 0.5
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub defaults: Settings,
+    /// Applied in order on top of the defaults, every one that matches the input counts.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<FileSettings>,
+}
+
+impl Config {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text =
+            std::fs::read_to_string(path).map_err(|error| Error::Io(path.to_path_buf(), error))?;
+        let invalid = |error: String| Error::Config(format!("{}: {}", path.display(), error));
+
+        match ConfigFormat::of(path) {
+            ConfigFormat::Toml => toml::from_str(&text).map_err(|error| invalid(error.to_string())),
+            ConfigFormat::Ron => ron_options()
+                .from_str(&text)
+                .map_err(|error| invalid(error.to_string())),
+        }
+    }
+
+    pub fn to_string(&self, format: ConfigFormat) -> Result<String> {
+        let result = match format {
+            ConfigFormat::Toml => toml::to_string_pretty(self).map_err(|error| error.to_string()),
+            ConfigFormat::Ron => ron_options()
+                .to_string_pretty(self, ron::ser::PrettyConfig::default())
+                .map_err(|error| error.to_string()),
+        };
+        result.map_err(Error::Config)
+    }
+
+    /// The defaults with the settings of every matching file on top.
+    pub fn settings_for(&self, input: &Path) -> Result<Settings> {
+        let mut settings = self.defaults.clone();
+        for file in &self.files {
+            if file.matches(input)? {
+                settings.merge(&file.settings);
+            }
+        }
+        Ok(settings)
+    }
+}
+
+// lets RON files write `scale: 2.0` instead of `scale: Some(2.0)`
+fn ron_options() -> ron::Options {
+    ron::Options::default().with_default_extension(ron::extensions::Extensions::IMPLICIT_SOME)
+}
@@ -0,0 +1,405 @@
+use std::fmt;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::rotation::{RotationError, VoxRotation};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Axis {
+    PosX,
+    NegX,
+    PosY,
+    NegY,
+    PosZ,
+    NegZ,
+}
+
+impl Axis {
+    /// Accepts `x`, `+x`, `-x` and so on.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "x" | "+x" => Some(Axis::PosX),
+            "-x" => Some(Axis::NegX),
+            "y" | "+y" => Some(Axis::PosY),
+            "-y" => Some(Axis::NegY),
+            "z" | "+z" => Some(Axis::PosZ),
+            "-z" => Some(Axis::NegZ),
+            _ => None,
+        }
+    }
+
+    pub fn to_vec3(self) -> Vec3 {
+        match self {
+            Axis::PosX => Vec3::X,
+            Axis::NegX => Vec3::NEG_X,
+            Axis::PosY => Vec3::Y,
+            Axis::NegY => Vec3::NEG_Y,
+            Axis::PosZ => Vec3::Z,
+            Axis::NegZ => Vec3::NEG_Z,
+        }
+    }
+}
+
+impl fmt::Display for Axis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Axis::PosX => "+x",
+            Axis::NegX => "-x",
+            Axis::PosY => "+y",
+            Axis::NegY => "-y",
+            Axis::PosZ => "+z",
+            Axis::NegZ => "-z",
+        };
+        f.write_str(name)
+    }
+}
+
+impl TryFrom<String> for Axis {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Axis::parse(&value)
+            .ok_or_else(|| format!("expected an axis like +y or -z, got {:?}", value))
+    }
+}
+
+impl From<Axis> for String {
+    fn from(axis: Axis) -> Self {
+        axis.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Handedness {
+    #[default]
+    Right,
+    Left,
+}
+
+/// Describes the axes of a source asset: which way is up, which way a character faces and
+/// whether the axes are right or left handed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoordinateSystem {
+    pub up: Axis,
+    pub forward: Axis,
+    pub handedness: Handedness,
+}
+
+impl Default for CoordinateSystem {
+    /// glTF: +Y up, characters face +Z, right handed.
+    ///
+    /// This is rotated onto MagicaVoxel rather than mirrored by swapping Y and Z, as earlier
+    /// versions did. `-Z` forward and left handed gives the swap back.
+    fn default() -> Self {
+        Self {
+            up: Axis::PosY,
+            forward: Axis::PosZ,
+            handedness: Handedness::Right,
+        }
+    }
+}
+
+impl CoordinateSystem {
+    /// MagicaVoxel: +Z up, characters face the default camera at -Y, right handed.
+    pub const MAGICA: Self = Self {
+        up: Axis::PosZ,
+        forward: Axis::NegY,
+        handedness: Handedness::Right,
+    };
+
+    /// Right, up and forward of a character standing in this coordinate system.
+    fn frame(&self) -> Option<[Vec3; 3]> {
+        let up = self.up.to_vec3();
+        let forward = self.forward.to_vec3();
+        if up.cross(forward) == Vec3::ZERO {
+            return None;
+        }
+
+        let right = match self.handedness {
+            Handedness::Right => forward.cross(up),
+            Handedness::Left => up.cross(forward),
+        };
+        Some([right, up, forward])
+    }
+}
+
+/// Change of basis from the coordinate system of a source asset to MagicaVoxel.
+///
+/// Every position, size, rotation and voxel coordinate written to a `.vox` goes through this,
+/// so no other code has to know which axes are swapped or flipped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Basis {
+    /// Maps source vectors to MagicaVoxel vectors. A reflection when the handedness differs.
+    to_magica: VoxRotation,
+}
+
+impl Default for Basis {
+    fn default() -> Self {
+        Self::new(CoordinateSystem::default()).unwrap()
+    }
+}
+
+impl Basis {
+    /// `None` when up and forward lie on the same axis.
+    pub fn new(source: CoordinateSystem) -> Option<Self> {
+        let [right, up, forward] = source.frame()?;
+        let [magica_right, magica_up, magica_forward] = CoordinateSystem::MAGICA.frame()?;
+
+        // send right, up and forward of the source to the same directions in magicavoxel
+        let source = Mat3::from_cols(right, up, forward);
+        let target = Mat3::from_cols(magica_right, magica_up, magica_forward);
+        Some(Self {
+            to_magica: VoxRotation::from_mat3(target * source.transpose()),
+        })
+    }
+
+    /// Unit vector pointing up in the source coordinate system.
+    pub fn source_up(&self) -> Vec3 {
+        self.to_magica.inverse() * Vec3::Z
+    }
+
+    /// Whether the source and magicavoxel differ in handedness.
+    pub fn is_reflection(&self) -> bool {
+        self.to_magica.is_reflection()
+    }
+
+    pub fn position(&self, position: Vec3) -> Vec3 {
+        self.to_magica * position
+    }
+
+    pub fn position_from_magica(&self, position: Vec3) -> Vec3 {
+        self.to_magica.inverse() * position
+    }
+
+    /// Size of a model along the magicavoxel axes.
+    pub fn size(&self, size: UVec3) -> UVec3 {
+        (self.to_magica.transform_ivec3(size.as_ivec3()))
+            .abs()
+            .as_uvec3()
+    }
+
+    /// Expresses a rotation of the source in magicavoxel axes.
+    pub fn rotation_to_magica(&self, rotation: VoxRotation) -> VoxRotation {
+        self.to_magica * rotation * self.to_magica.inverse()
+    }
+
+    pub fn rotation_from_magica(&self, rotation: VoxRotation) -> VoxRotation {
+        self.to_magica.inverse() * rotation * self.to_magica
+    }
+
+    /// Decodes a `_r` byte into a rotation in source axes, fails for mirror matrices.
+    pub fn quat_from_magica(&self, byte: u8) -> Result<Quat, RotationError> {
+        self.rotation_from_magica(VoxRotation::from_byte(byte)?)
+            .to_quat()
+            .ok_or(RotationError::Reflection(byte))
+    }
+
+    /// Moves a voxel of a model with the given source `size` to its coordinate in the
+    /// magicavoxel model, flipped axes are counted from the other end.
+    pub fn voxel(&self, voxel: UVec3, size: UVec3) -> UVec3 {
+        let mapped = self.to_magica.transform_ivec3(voxel.as_ivec3());
+        let offset = self.voxel_offset(size);
+        (mapped + offset).as_uvec3()
+    }
+
+    /// Offset, in source voxels from the minimum corner of a model, of the voxel MagicaVoxel
+    /// places on the translation of the model: the one at half the size in magicavoxel axes.
+    pub fn pivot(&self, size: UVec3) -> IVec3 {
+        let magica_pivot = (self.size(size) / 2).as_ivec3();
+        self.to_magica
+            .inverse()
+            .transform_ivec3(magica_pivot - self.voxel_offset(size))
+    }
+
+    fn voxel_offset(&self, size: UVec3) -> IVec3 {
+        let far_corner = self.to_magica.transform_ivec3(size.as_ivec3() - IVec3::ONE);
+        (-far_corner).max(IVec3::ZERO)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn system(up: Axis, forward: Axis, handedness: Handedness) -> CoordinateSystem {
+        CoordinateSystem {
+            up,
+            forward,
+            handedness,
+        }
+    }
+
+    /// The default in both handednesses, and a few other mappings.
+    fn bases() -> Vec<Basis> {
+        [
+            CoordinateSystem::default(),
+            system(Axis::PosY, Axis::PosZ, Handedness::Left),
+            CoordinateSystem::MAGICA,
+            system(Axis::NegX, Axis::PosY, Handedness::Right),
+            system(Axis::PosX, Axis::NegZ, Handedness::Left),
+        ]
+        .into_iter()
+        .map(|system| Basis::new(system).unwrap())
+        .collect()
+    }
+
+    #[test]
+    fn default_rotates_y_up_to_z_up() {
+        let basis = Basis::default();
+        assert!(!basis.is_reflection());
+        assert_eq!(
+            basis.position(Vec3::new(1.0, 2.0, 3.0)),
+            Vec3::new(1.0, -3.0, 2.0)
+        );
+        assert_eq!(basis.source_up(), Vec3::Y);
+    }
+
+    #[test]
+    fn old_mapping_swaps_y_and_z() {
+        let basis = Basis::new(system(Axis::PosY, Axis::NegZ, Handedness::Left)).unwrap();
+        assert!(basis.is_reflection());
+        assert_eq!(
+            basis.position(Vec3::new(1.0, 2.0, 3.0)),
+            Vec3::new(1.0, 3.0, 2.0)
+        );
+    }
+
+    #[test]
+    fn magica_is_identity() {
+        let basis = Basis::new(CoordinateSystem::MAGICA).unwrap();
+        assert_eq!(
+            basis.position(Vec3::new(1.0, 2.0, 3.0)),
+            Vec3::new(1.0, 2.0, 3.0)
+        );
+        assert_eq!(basis.size(UVec3::new(1, 2, 3)), UVec3::new(1, 2, 3));
+    }
+
+    #[test]
+    fn rejects_parallel_axes() {
+        assert_eq!(
+            Basis::new(system(Axis::PosY, Axis::NegY, Handedness::Right)),
+            None
+        );
+    }
+
+    #[test]
+    fn positions_round_trip() {
+        let position = Vec3::new(1.5, -2.0, 3.25);
+        for basis in bases() {
+            let magica = basis.position(position);
+            assert_eq!(magica.abs().max_element(), 3.25);
+            assert_eq!(basis.position_from_magica(magica), position);
+        }
+    }
+
+    #[test]
+    fn sizes_follow_positions() {
+        let size = UVec3::new(2, 3, 5);
+        for basis in bases() {
+            let expected = basis.position(size.as_vec3()).abs().as_uvec3();
+            assert_eq!(basis.size(size), expected, "{:?}", basis);
+            assert_eq!(basis.size(size).element_product(), size.element_product());
+        }
+    }
+
+    #[test]
+    fn voxels_fill_the_magica_model() {
+        let size = UVec3::new(2, 3, 5);
+        for basis in bases() {
+            let magica_size = basis.size(size);
+            let mut seen = vec![false; size.element_product() as usize];
+            for x in 0..size.x {
+                for y in 0..size.y {
+                    for z in 0..size.z {
+                        let voxel = UVec3::new(x, y, z);
+                        let magica = basis.voxel(voxel, size);
+                        assert!(magica.cmplt(magica_size).all(), "{:?}", basis);
+
+                        let index =
+                            magica.x + (magica.y + magica.z * magica_size.y) * magica_size.x;
+                        assert!(
+                            !seen[index as usize],
+                            "{:?} maps two voxels to {}",
+                            basis, magica
+                        );
+                        seen[index as usize] = true;
+
+                        // neighbours stay neighbours along the mapped axis
+                        let step = basis.position(Vec3::X).as_ivec3();
+                        if x + 1 < size.x {
+                            let next = basis.voxel(voxel + UVec3::X, size);
+                            assert_eq!(next.as_ivec3() - magica.as_ivec3(), step);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn pivot_lands_on_the_magica_center() {
+        for size in [UVec3::new(2, 3, 5), UVec3::new(4, 1, 6), UVec3::ONE] {
+            for basis in bases() {
+                let pivot = basis.pivot(size);
+                assert!(pivot.cmpge(IVec3::ZERO).all() && pivot.cmplt(size.as_ivec3()).all());
+                assert_eq!(
+                    basis.voxel(pivot.as_uvec3(), size),
+                    basis.size(size) / 2,
+                    "{:?} with size {}",
+                    basis,
+                    size
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rotations_round_trip() {
+        let position = Vec3::new(1.0, 2.0, 3.0);
+        for basis in bases() {
+            for rotation in VoxRotation::all() {
+                let magica = basis.rotation_to_magica(rotation);
+                assert_eq!(magica.is_reflection(), rotation.is_reflection());
+                assert_eq!(basis.rotation_from_magica(magica), rotation);
+                assert_eq!(
+                    magica * basis.position(position),
+                    basis.position(rotation * position)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn quats_from_magica_match_the_rotation() {
+        let position = Vec3::new(1.0, 2.0, 3.0);
+        for basis in bases() {
+            for magica in VoxRotation::all() {
+                let result = basis.quat_from_magica(magica.to_byte());
+                if magica.is_reflection() {
+                    assert_eq!(result, Err(RotationError::Reflection(magica.to_byte())));
+                    continue;
+                }
+
+                let rotated = basis.position(result.unwrap() * position);
+                assert!(
+                    rotated.abs_diff_eq(magica * basis.position(position), 1e-5),
+                    "{:?} with {:?}",
+                    basis,
+                    magica
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn axes_parse_and_print() {
+        for axis in ["+x", "-x", "+y", "-y", "+z", "-z"] {
+            assert_eq!(Axis::parse(axis).unwrap().to_string(), axis);
+        }
+        assert_eq!(Axis::parse("Y"), Some(Axis::PosY));
+        assert_eq!(Axis::parse("w"), None);
+    }
+}
@@ -0,0 +1,75 @@
+use std::{cell::RefCell, fmt, io, path::PathBuf};
+
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing a file failed.
+    Io(PathBuf, io::Error),
+    /// The input couldn't be imported as glTF.
+    Gltf(gltf::Error),
+    /// The glTF has no skin to convert, or not the one that was asked for.
+    MissingSkin(Option<String>),
+    /// A clip that was asked for doesn't exist.
+    MissingClip(String),
+    /// A bone that was asked for doesn't exist.
+    MissingBone(String),
+    /// The input uses something the converter can't handle.
+    Unsupported(String),
+    /// A `.vox` file or scene graph that can't be read or written.
+    InvalidVox(String),
+    /// A config file or setting that doesn't make sense.
+    Config(String),
+    /// A batch job that couldn't run: the thread pool failed, the conversion panicked or its
+    /// output collides with another one.
+    Batch(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(path, error) => write!(f, "{}: {}", path.display(), error),
+            Error::Gltf(error) => write!(f, "invalid glTF: {}", error),
+            Error::MissingSkin(None) => write!(f, "the glTF has no skin"),
+            Error::MissingSkin(Some(skin)) => write!(f, "skin {} not found", skin),
+            Error::MissingClip(clip) => write!(f, "clip {} not found", clip),
+            Error::MissingBone(bone) => write!(f, "bone {:?} not found", bone),
+            Error::Unsupported(message) => write!(f, "unsupported: {}", message),
+            Error::InvalidVox(message) => write!(f, "invalid vox: {}", message),
+            Error::Config(message) => write!(f, "invalid config: {}", message),
+            Error::Batch(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(_, error) => Some(error),
+            Error::Gltf(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<gltf::Error> for Error {
+    fn from(error: gltf::Error) -> Self {
+        Error::Gltf(error)
+    }
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+thread_local! {
+    static WARNINGS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Prints a warning and remembers it for `take_warnings`, so batch jobs can count them.
+pub fn warn(message: impl Into<String>) {
+    let message = message.into();
+    eprintln!("warning: {}", message);
+    WARNINGS.with(|warnings| warnings.borrow_mut().push(message));
+}
+
+/// The warnings of the current thread since the last call.
+pub fn take_warnings() -> Vec<String> {
+    WARNINGS.with(|warnings| std::mem::take(&mut *warnings.borrow_mut()))
+}
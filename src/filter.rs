@@ -0,0 +1,106 @@
+use crate::rig::{Bone, Skeleton};
+
+/// Matches bone names, ignoring case.
+///
+/// Written as a glob like `*twist*`, or as a regex after `re:` like `re:^finger\d+$`.
+#[derive(Debug, Clone)]
+pub enum BonePattern {
+    Glob(glob::Pattern),
+    Regex(regex::Regex),
+}
+
+impl BonePattern {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.strip_prefix("re:") {
+            Some(regex) => regex::RegexBuilder::new(regex)
+                .case_insensitive(true)
+                .build()
+                .map(BonePattern::Regex)
+                .map_err(|error| format!("{:?}: {}", value, error)),
+            None => glob::Pattern::new(value)
+                .map(BonePattern::Glob)
+                .map_err(|error| format!("{:?}: {}", value, error)),
+        }
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        match self {
+            BonePattern::Glob(pattern) => pattern.matches_with(
+                name,
+                glob::MatchOptions {
+                    case_sensitive: false,
+                    ..Default::default()
+                },
+            ),
+            BonePattern::Regex(regex) => regex.is_match(name),
+        }
+    }
+}
+
+/// Which bones become parts of their own.
+#[derive(Debug, Clone, Default)]
+pub struct BoneFilter {
+    /// When not empty, only bones matching one of these are kept.
+    pub include: Vec<BonePattern>,
+    pub exclude: Vec<BonePattern>,
+    /// Merge the voxels of a filtered bone into the part of its parent instead of dropping them.
+    pub collapse: bool,
+}
+
+impl BoneFilter {
+    /// Patterns can match any of `Bone::names`.
+    pub fn keeps(&self, bone: &Bone) -> bool {
+        let names = bone.names();
+        let matches = |pattern: &BonePattern| names.iter().any(|name| pattern.matches(name));
+        (self.include.is_empty() || self.include.iter().any(matches))
+            && !self.exclude.iter().any(matches)
+    }
+}
+
+/// The kept bones of a skeleton, every one parented to its closest kept ancestor.
+#[derive(Debug, Clone)]
+pub struct FilteredSkeleton {
+    pub skeleton: Skeleton,
+    /// Index into the original skeleton of every bone.
+    pub bones: Vec<usize>,
+    /// Index into `skeleton` of the part that gets the voxels of every original bone, `None` for
+    /// bones that are dropped.
+    pub owners: Vec<Option<usize>>,
+}
+
+impl FilteredSkeleton {
+    pub fn new(skeleton: &Skeleton, keep: &[bool], collapse: bool) -> Self {
+        let mut filtered = Skeleton { bones: vec![] };
+        let mut bones = vec![];
+        // closest kept bone at or above every original bone
+        let mut kept_ancestors: Vec<Option<usize>> = Vec::with_capacity(skeleton.bones.len());
+        let mut owners = Vec::with_capacity(skeleton.bones.len());
+
+        for (index, bone) in skeleton.bones.iter().enumerate() {
+            let parent = bone.parent.and_then(|parent| kept_ancestors[parent]);
+            if keep[index] {
+                let new_index = filtered.bones.len();
+                if let Some(parent) = parent {
+                    filtered.bones[parent].children.push(new_index);
+                }
+                filtered.bones.push(Bone {
+                    parent,
+                    children: vec![],
+                    ..bone.clone()
+                });
+                bones.push(index);
+                kept_ancestors.push(Some(new_index));
+                owners.push(Some(new_index));
+            } else {
+                kept_ancestors.push(parent);
+                owners.push(parent.filter(|_| collapse));
+            }
+        }
+
+        Self {
+            skeleton: filtered,
+            bones,
+            owners,
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+use crate::{
+    animation::LoopMode,
+    error::{Error, Result},
+};
+
+/// Describes the `.vox` files written for every sampled frame of a clip.
+#[derive(Debug, Serialize)]
+pub struct FrameManifest {
+    pub clip: Option<String>,
+    pub fps: f32,
+    pub loop_mode: LoopMode,
+    pub frames: Vec<FrameEntry>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FrameEntry {
+    /// File name, relative to the manifest.
+    pub file: String,
+    /// Time in the source clip in seconds.
+    pub time: f32,
+    /// How long the frame is shown in seconds.
+    pub duration: f32,
+    /// Extracted root motion of the frame in glTF units, only written when it was extracted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub root_offset: Option<[f32; 3]>,
+}
+
+impl FrameManifest {
+    pub fn new(
+        clip: Option<&str>,
+        fps: f32,
+        loop_mode: LoopMode,
+        prefix: &str,
+        times: &[f32],
+    ) -> Self {
+        let frames = times
+            .iter()
+            .enumerate()
+            .map(|(i, time)| FrameEntry {
+                file: format!("{}_{:03}.vox", prefix, i),
+                time: *time,
+                duration: 1.0 / fps,
+                root_offset: None,
+            })
+            .collect();
+
+        Self {
+            clip: clip.map(|clip| clip.to_string()),
+            fps,
+            loop_mode,
+            frames,
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let file = BufWriter::new(
+            File::create(path).map_err(|error| Error::Io(path.to_path_buf(), error))?,
+        );
+        serde_json::to_writer_pretty(file, self)
+            .map_err(|error| Error::Io(path.to_path_buf(), error.into()))
+    }
+}
+
+/// Directory and file name prefix of the per-frame files, e.g. `robot_walk` for
+/// `robot_walk_000.vox`.
+///
+/// The prefix is the name of the output file followed by the clip name when there is one, so
+/// outputs next to each other don't overwrite each other's frames.
+pub fn frame_prefix(output: &Path, clip: Option<&str>) -> (PathBuf, String) {
+    let directory = output.parent().map(Path::to_path_buf).unwrap_or_default();
+    let stem = output.file_stem().map_or_else(
+        || "frame".to_string(),
+        |stem| stem.to_string_lossy().to_string(),
+    );
+    let name = match clip {
+        Some(clip) => format!("{}_{}", stem, clip),
+        None => stem,
+    };
+
+    let prefix = name
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect();
+
+    (directory, prefix)
+}
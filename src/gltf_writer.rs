@@ -0,0 +1,310 @@
+use std::{fs, io, path::Path};
+
+use bevy::prelude::*;
+use gltf::animation::Interpolation;
+use serde_json::{json, Value};
+
+use crate::error::{Error, Result};
+
+const UNSIGNED_SHORT: u32 = 5123;
+const UNSIGNED_INT: u32 = 5125;
+const FLOAT: u32 = 5126;
+
+/// Values of an animation channel, one per keyframe.
+#[derive(Debug, Clone)]
+pub enum ChannelValues {
+    Translation(Vec<Vec3>),
+    Rotation(Vec<Quat>),
+    Scale(Vec<Vec3>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Channel {
+    pub node: usize,
+    /// In seconds.
+    pub times: Vec<f32>,
+    /// Three per keyframe for cubic splines: in tangent, value and out tangent.
+    pub values: ChannelValues,
+    pub interpolation: Interpolation,
+}
+
+/// A glTF document being put together, the data of every accessor goes into a single buffer.
+#[derive(Debug, Default)]
+pub struct GltfWriter {
+    buffer: Vec<u8>,
+    buffer_views: Vec<Value>,
+    accessors: Vec<Value>,
+    materials: Vec<Value>,
+    meshes: Vec<Value>,
+    nodes: Vec<Value>,
+    skins: Vec<Value>,
+    animations: Vec<Value>,
+}
+
+impl GltfWriter {
+    pub fn scalars(&mut self, values: &[f32]) -> usize {
+        let (min, max) = bounds(values.iter().map(|value| [*value]));
+        self.accessor(
+            FLOAT,
+            "SCALAR",
+            values.len(),
+            &floats(values),
+            Some((min, max)),
+        )
+    }
+
+    pub fn vec3s(&mut self, values: &[Vec3]) -> usize {
+        let (min, max) = bounds(values.iter().map(|value| value.to_array()));
+        let data = floats(
+            &values
+                .iter()
+                .flat_map(|value| value.to_array())
+                .collect::<Vec<_>>(),
+        );
+        self.accessor(FLOAT, "VEC3", values.len(), &data, Some((min, max)))
+    }
+
+    pub fn vec4s(&mut self, values: &[Vec4]) -> usize {
+        let data = floats(
+            &values
+                .iter()
+                .flat_map(|value| value.to_array())
+                .collect::<Vec<_>>(),
+        );
+        self.accessor(FLOAT, "VEC4", values.len(), &data, None)
+    }
+
+    pub fn mat4s(&mut self, values: &[Mat4]) -> usize {
+        let data = floats(
+            &values
+                .iter()
+                .flat_map(|value| value.to_cols_array())
+                .collect::<Vec<_>>(),
+        );
+        self.accessor(FLOAT, "MAT4", values.len(), &data, None)
+    }
+
+    /// Joint indices of every vertex, for `JOINTS_0`.
+    pub fn joints(&mut self, values: &[[u16; 4]]) -> usize {
+        let data = values
+            .iter()
+            .flatten()
+            .flat_map(|value| value.to_le_bytes())
+            .collect::<Vec<_>>();
+        self.accessor(UNSIGNED_SHORT, "VEC4", values.len(), &data, None)
+    }
+
+    pub fn indices(&mut self, values: &[u32]) -> usize {
+        let data = values
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect::<Vec<_>>();
+        self.accessor(UNSIGNED_INT, "SCALAR", values.len(), &data, None)
+    }
+
+    /// The material of every mesh: plain white, so the vertex colors show as they are.
+    fn material(&mut self) -> usize {
+        if self.materials.is_empty() {
+            self.materials.push(json!({
+                "name": "voxel",
+                "pbrMetallicRoughness": {
+                    "metallicFactor": 0.0,
+                    "roughnessFactor": 1.0,
+                },
+            }));
+        }
+        0
+    }
+
+    /// A mesh of one triangle list, `attributes` maps names like `POSITION` to accessors.
+    pub fn mesh(&mut self, name: Option<&str>, attributes: Value, indices: usize) -> usize {
+        let material = self.material();
+        let mut mesh = json!({
+            "primitives": [{
+                "attributes": attributes,
+                "indices": indices,
+                "material": material,
+            }],
+        });
+        if let Some(name) = name {
+            mesh["name"] = json!(name);
+        }
+        self.meshes.push(mesh);
+        self.meshes.len() - 1
+    }
+
+    /// Adds a node as written in the glTF, like `{"name": "arm", "children": [2, 3]}`.
+    pub fn node(&mut self, node: Value) -> usize {
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+
+    pub fn skin(&mut self, joints: &[usize], inverse_bind_matrices: &[Mat4]) -> usize {
+        let inverse_bind_matrices = self.mat4s(inverse_bind_matrices);
+        self.skins.push(json!({
+            "joints": joints,
+            "inverseBindMatrices": inverse_bind_matrices,
+        }));
+        self.skins.len() - 1
+    }
+
+    pub fn animation(&mut self, name: &str, channels: &[Channel]) {
+        let mut samplers = vec![];
+        let mut targets = vec![];
+        for channel in channels {
+            let input = self.scalars(&channel.times);
+            let (path, output) = match &channel.values {
+                ChannelValues::Translation(values) => ("translation", self.vec3s(values)),
+                ChannelValues::Scale(values) => ("scale", self.vec3s(values)),
+                ChannelValues::Rotation(values) => {
+                    let values = values
+                        .iter()
+                        .map(|rotation| Vec4::from_array(rotation.to_array()))
+                        .collect::<Vec<_>>();
+                    ("rotation", self.vec4s(&values))
+                }
+            };
+
+            targets.push(json!({
+                "sampler": samplers.len(),
+                "target": { "node": channel.node, "path": path },
+            }));
+            samplers.push(json!({
+                "input": input,
+                "output": output,
+                "interpolation": match channel.interpolation {
+                    Interpolation::Linear => "LINEAR",
+                    Interpolation::Step => "STEP",
+                    Interpolation::CubicSpline => "CUBICSPLINE",
+                },
+            }));
+        }
+
+        self.animations.push(json!({
+            "name": name,
+            "channels": targets,
+            "samplers": samplers,
+        }));
+    }
+
+    /// Writes a `.glb`, or for any other extension a `.gltf` with a `.bin` next to it.
+    pub fn save(self, path: &Path, roots: &[usize]) -> Result<()> {
+        let io_error = |error| Error::Io(path.to_path_buf(), error);
+        let glb = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("glb"));
+
+        let mut buffer = json!({ "byteLength": self.buffer.len() });
+        let bin_path = path.with_extension("bin");
+        if !glb {
+            let uri = bin_path.file_name().ok_or_else(|| {
+                io_error(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "the output has no file name",
+                ))
+            })?;
+            buffer["uri"] = json!(uri.to_string_lossy());
+        }
+
+        let mut document = json!({
+            "asset": { "version": "2.0", "generator": "skeleton-to-vox" },
+            "scene": 0,
+            "scenes": [{ "nodes": roots }],
+            "buffers": [buffer],
+            "bufferViews": self.buffer_views,
+            "accessors": self.accessors,
+            "nodes": self.nodes,
+        });
+        for (key, values) in [
+            ("materials", self.materials),
+            ("meshes", self.meshes),
+            ("skins", self.skins),
+            ("animations", self.animations),
+        ] {
+            if !values.is_empty() {
+                document[key] = Value::Array(values);
+            }
+        }
+        let json = serde_json::to_vec(&document).map_err(|error| io_error(error.into()))?;
+
+        if !glb {
+            fs::write(path, json).map_err(io_error)?;
+            return fs::write(&bin_path, &self.buffer)
+                .map_err(|error| Error::Io(bin_path.clone(), error));
+        }
+
+        // chunks are padded to 4 bytes, json with spaces
+        let pad = |mut data: Vec<u8>, with: u8| {
+            data.resize(data.len().next_multiple_of(4), with);
+            data
+        };
+        let json = pad(json, b' ');
+        let bin = pad(self.buffer, 0);
+        let length = 12 + 8 + json.len() + 8 + bin.len();
+
+        let mut glb = Vec::with_capacity(length);
+        glb.extend(b"glTF");
+        glb.extend(2u32.to_le_bytes());
+        glb.extend((length as u32).to_le_bytes());
+        for (kind, chunk) in [(b"JSON", &json), (b"BIN\0", &bin)] {
+            glb.extend((chunk.len() as u32).to_le_bytes());
+            glb.extend(kind);
+            glb.extend(chunk);
+        }
+        fs::write(path, glb).map_err(io_error)
+    }
+
+    fn accessor(
+        &mut self,
+        component_type: u32,
+        kind: &str,
+        count: usize,
+        data: &[u8],
+        bounds: Option<(Vec<f32>, Vec<f32>)>,
+    ) -> usize {
+        // views start at a multiple of 4 bytes so every component type is aligned
+        self.buffer.resize(self.buffer.len().next_multiple_of(4), 0);
+        let view = json!({
+            "buffer": 0,
+            "byteOffset": self.buffer.len(),
+            "byteLength": data.len(),
+        });
+        self.buffer.extend_from_slice(data);
+        self.buffer_views.push(view);
+
+        let mut accessor = json!({
+            "bufferView": self.buffer_views.len() - 1,
+            "componentType": component_type,
+            "count": count,
+            "type": kind,
+        });
+        if let Some((min, max)) = bounds {
+            accessor["min"] = json!(min);
+            accessor["max"] = json!(max);
+        }
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+}
+
+fn floats(values: &[f32]) -> Vec<u8> {
+    values
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect()
+}
+
+/// Per component minimum and maximum, `POSITION` and animation inputs need them.
+fn bounds<const N: usize>(values: impl Iterator<Item = [f32; N]>) -> (Vec<f32>, Vec<f32>) {
+    let (min, max) = values.fold(
+        ([f32::INFINITY; N], [f32::NEG_INFINITY; N]),
+        |(mut min, mut max), value| {
+            for i in 0..N {
+                min[i] = min[i].min(value[i]);
+                max[i] = max[i].max(value[i]);
+            }
+            (min, max)
+        },
+    );
+    (min.to_vec(), max.to_vec())
+}
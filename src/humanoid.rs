@@ -0,0 +1,354 @@
+use std::fmt;
+
+use crate::{layers::Side, rig::Skeleton};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Finger {
+    Thumb,
+    Index,
+    Middle,
+    Ring,
+    Pinky,
+}
+
+impl Finger {
+    pub const ALL: [Finger; 5] = [
+        Finger::Thumb,
+        Finger::Index,
+        Finger::Middle,
+        Finger::Ring,
+        Finger::Pinky,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Finger::Thumb => "thumb",
+            Finger::Index => "index",
+            Finger::Middle => "middle",
+            Finger::Ring => "ring",
+            Finger::Pinky => "pinky",
+        }
+    }
+}
+
+/// A bone of the canonical humanoid skeleton that the presets map onto.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HumanBone {
+    Hips,
+    Spine,
+    Chest,
+    UpperChest,
+    Neck,
+    Head,
+    Shoulder(Side),
+    UpperArm(Side),
+    LowerArm(Side),
+    Hand(Side),
+    UpperLeg(Side),
+    LowerLeg(Side),
+    Foot(Side),
+    Toes(Side),
+    /// A finger segment, counting from the hand starting at 1.
+    Finger(Side, Finger, u8),
+}
+
+impl HumanBone {
+    /// Every bone of the skeleton, parents before their children.
+    pub fn all() -> Vec<HumanBone> {
+        let mut bones = vec![
+            HumanBone::Hips,
+            HumanBone::Spine,
+            HumanBone::Chest,
+            HumanBone::UpperChest,
+            HumanBone::Neck,
+            HumanBone::Head,
+        ];
+        for side in [Side::Left, Side::Right] {
+            bones.extend([
+                HumanBone::Shoulder(side),
+                HumanBone::UpperArm(side),
+                HumanBone::LowerArm(side),
+                HumanBone::Hand(side),
+            ]);
+            for finger in Finger::ALL {
+                bones.extend((1..=3).map(|segment| HumanBone::Finger(side, finger, segment)));
+            }
+            bones.extend([
+                HumanBone::UpperLeg(side),
+                HumanBone::LowerLeg(side),
+                HumanBone::Foot(side),
+                HumanBone::Toes(side),
+            ]);
+        }
+        bones
+    }
+
+    /// Parses the names written by `Display`, like `hips` or `left_index_2`.
+    pub fn parse(name: &str) -> Option<Self> {
+        let name = name.to_ascii_lowercase();
+        HumanBone::all()
+            .into_iter()
+            .find(|bone| bone.to_string() == name)
+    }
+}
+
+impl fmt::Display for HumanBone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let side = |side: &Side| match side {
+            Side::Left => "left",
+            Side::Right => "right",
+        };
+        match self {
+            HumanBone::Hips => write!(f, "hips"),
+            HumanBone::Spine => write!(f, "spine"),
+            HumanBone::Chest => write!(f, "chest"),
+            HumanBone::UpperChest => write!(f, "upper_chest"),
+            HumanBone::Neck => write!(f, "neck"),
+            HumanBone::Head => write!(f, "head"),
+            HumanBone::Shoulder(s) => write!(f, "{}_shoulder", side(s)),
+            HumanBone::UpperArm(s) => write!(f, "{}_upper_arm", side(s)),
+            HumanBone::LowerArm(s) => write!(f, "{}_lower_arm", side(s)),
+            HumanBone::Hand(s) => write!(f, "{}_hand", side(s)),
+            HumanBone::UpperLeg(s) => write!(f, "{}_upper_leg", side(s)),
+            HumanBone::LowerLeg(s) => write!(f, "{}_lower_leg", side(s)),
+            HumanBone::Foot(s) => write!(f, "{}_foot", side(s)),
+            HumanBone::Toes(s) => write!(f, "{}_toes", side(s)),
+            HumanBone::Finger(s, finger, segment) => {
+                write!(f, "{}_{}_{}", side(s), finger.name(), segment)
+            }
+        }
+    }
+}
+
+/// Naming conventions of common rigs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BonePreset {
+    /// Mixamo, `mixamorig:LeftForeArm`.
+    Mixamo,
+    /// The UE4 and UE5 mannequins, `lowerarm_l`.
+    Unreal,
+    /// The deform bones of Blender Rigify, `DEF-forearm.L`.
+    Rigify,
+}
+
+impl BonePreset {
+    pub const ALL: [BonePreset; 3] = [BonePreset::Mixamo, BonePreset::Unreal, BonePreset::Rigify];
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "mixamo" => Some(BonePreset::Mixamo),
+            "unreal" | "ue4" | "ue5" => Some(BonePreset::Unreal),
+            "rigify" => Some(BonePreset::Rigify),
+            _ => None,
+        }
+    }
+
+    /// The canonical bone for a bone name of this rig, `None` for twist and helper bones.
+    pub fn map(self, name: &str) -> Option<HumanBone> {
+        match self {
+            BonePreset::Mixamo => mixamo(name),
+            BonePreset::Unreal => unreal(name),
+            BonePreset::Rigify => rigify(name),
+        }
+    }
+
+    /// The preset that knows most of the bones, out of those that know the hips.
+    pub fn detect(skeleton: &Skeleton) -> Option<Self> {
+        let names = skeleton
+            .bones
+            .iter()
+            .filter_map(|bone| bone.name.as_deref())
+            .collect::<Vec<_>>();
+        BonePreset::ALL
+            .into_iter()
+            .filter(|preset| {
+                names
+                    .iter()
+                    .any(|name| preset.map(name) == Some(HumanBone::Hips))
+            })
+            .max_by_key(|preset| {
+                names
+                    .iter()
+                    .filter(|name| preset.map(name).is_some())
+                    .count()
+            })
+    }
+}
+
+fn mixamo(name: &str) -> Option<HumanBone> {
+    // `mixamorig:`, or `mixamorig1:` and so on when several rigs were imported into one file
+    let name = match name.split_once(':') {
+        Some((prefix, name)) if prefix.starts_with("mixamorig") => name,
+        _ => name,
+    };
+
+    let bone = match name {
+        "Hips" => HumanBone::Hips,
+        "Spine" => HumanBone::Spine,
+        "Spine1" => HumanBone::Chest,
+        "Spine2" => HumanBone::UpperChest,
+        "Neck" => HumanBone::Neck,
+        "Head" => HumanBone::Head,
+        _ => {
+            let (side, name) = if let Some(name) = name.strip_prefix("Left") {
+                (Side::Left, name)
+            } else {
+                (Side::Right, name.strip_prefix("Right")?)
+            };
+            match name {
+                "Shoulder" => HumanBone::Shoulder(side),
+                "Arm" => HumanBone::UpperArm(side),
+                "ForeArm" => HumanBone::LowerArm(side),
+                "Hand" => HumanBone::Hand(side),
+                "UpLeg" => HumanBone::UpperLeg(side),
+                "Leg" => HumanBone::LowerLeg(side),
+                "Foot" => HumanBone::Foot(side),
+                "ToeBase" => HumanBone::Toes(side),
+                _ => {
+                    // `LeftHandIndex1`, the 4th segment is the tip helper
+                    let name = name.strip_prefix("Hand")?;
+                    finger(side, name, |finger| match finger {
+                        Finger::Thumb => "Thumb",
+                        Finger::Index => "Index",
+                        Finger::Middle => "Middle",
+                        Finger::Ring => "Ring",
+                        Finger::Pinky => "Pinky",
+                    })?
+                }
+            }
+        }
+    };
+    Some(bone)
+}
+
+fn unreal(name: &str) -> Option<HumanBone> {
+    let bone = match name {
+        "pelvis" => HumanBone::Hips,
+        "spine_01" => HumanBone::Spine,
+        "spine_02" => HumanBone::Chest,
+        "spine_03" => HumanBone::UpperChest,
+        "neck_01" => HumanBone::Neck,
+        "head" => HumanBone::Head,
+        _ => {
+            let (side, name) = if let Some(name) = name.strip_suffix("_l") {
+                (Side::Left, name)
+            } else {
+                (Side::Right, name.strip_suffix("_r")?)
+            };
+            match name {
+                "clavicle" => HumanBone::Shoulder(side),
+                "upperarm" => HumanBone::UpperArm(side),
+                "lowerarm" => HumanBone::LowerArm(side),
+                "hand" => HumanBone::Hand(side),
+                "thigh" => HumanBone::UpperLeg(side),
+                "calf" => HumanBone::LowerLeg(side),
+                "foot" => HumanBone::Foot(side),
+                "ball" => HumanBone::Toes(side),
+                // `index_01`
+                _ => finger(side, &name.replace("_0", ""), |finger| finger.name())?,
+            }
+        }
+    };
+    Some(bone)
+}
+
+fn rigify(name: &str) -> Option<HumanBone> {
+    let name = name.strip_prefix("DEF-")?;
+    let bone = match name {
+        "spine" => HumanBone::Hips,
+        "spine.001" => HumanBone::Spine,
+        "spine.002" => HumanBone::Chest,
+        "spine.003" => HumanBone::UpperChest,
+        "spine.004" => HumanBone::Neck,
+        "spine.006" => HumanBone::Head,
+        _ => {
+            let (side, name) = if let Some(name) = name.strip_suffix(".L") {
+                (Side::Left, name)
+            } else {
+                (Side::Right, name.strip_suffix(".R")?)
+            };
+            // the `.001` segments are twist bones
+            match name {
+                "shoulder" => HumanBone::Shoulder(side),
+                "upper_arm" => HumanBone::UpperArm(side),
+                "forearm" => HumanBone::LowerArm(side),
+                "hand" => HumanBone::Hand(side),
+                "thigh" => HumanBone::UpperLeg(side),
+                "shin" => HumanBone::LowerLeg(side),
+                "foot" => HumanBone::Foot(side),
+                "toe" => HumanBone::Toes(side),
+                // `thumb.01` and `f_index.01`
+                _ => finger(
+                    side,
+                    &name.trim_start_matches("f_").replace(".0", ""),
+                    |finger| finger.name(),
+                )?,
+            }
+        }
+    };
+    Some(bone)
+}
+
+/// Parses a finger name followed by a segment from 1 to 3, like `Index2`.
+fn finger(side: Side, name: &str, finger_name: fn(Finger) -> &'static str) -> Option<HumanBone> {
+    Finger::ALL.into_iter().find_map(|finger| {
+        let segment = name.strip_prefix(finger_name(finger))?.parse().ok()?;
+        (1..=3)
+            .contains(&segment)
+            .then_some(HumanBone::Finger(side, finger, segment))
+    })
+}
+
+/// Which naming convention a `BoneMap` uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PresetChoice {
+    /// Only the bones mapped by hand are known.
+    #[default]
+    None,
+    /// Picks one with `BonePreset::detect`.
+    Auto,
+    Preset(BonePreset),
+}
+
+impl PresetChoice {
+    /// Accepts `none`, `auto` or a name `BonePreset::parse` knows.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "none" => Some(PresetChoice::None),
+            "auto" => Some(PresetChoice::Auto),
+            _ => BonePreset::parse(value).map(PresetChoice::Preset),
+        }
+    }
+}
+
+/// How to find the canonical bones of a rig.
+#[derive(Debug, Clone, Default)]
+pub struct BoneMap {
+    pub preset: PresetChoice,
+    /// Bone names of the rig mapped by hand, these win over the preset.
+    pub custom: Vec<(String, HumanBone)>,
+}
+
+impl BoneMap {
+    /// Sets `Bone::human` of every bone this map knows, returns the preset that was used.
+    pub fn apply(&self, skeleton: &mut Skeleton) -> Option<BonePreset> {
+        let preset = match self.preset {
+            PresetChoice::None => None,
+            PresetChoice::Auto => BonePreset::detect(skeleton),
+            PresetChoice::Preset(preset) => Some(preset),
+        };
+
+        for bone in &mut skeleton.bones {
+            let Some(name) = bone.name.as_deref() else {
+                continue;
+            };
+            bone.human = self
+                .custom
+                .iter()
+                .find(|(custom, _)| custom == name)
+                .map(|(_, human)| *human)
+                .or_else(|| preset.and_then(|preset| preset.map(name)));
+        }
+        preset
+    }
+}
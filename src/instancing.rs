@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::rotation::VoxRotation;
+
+/// Size and sorted voxels of a model, two models with the same key look exactly the same.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct ModelKey {
+    size: [u32; 3],
+    voxels: Vec<[u8; 4]>,
+}
+
+impl ModelKey {
+    fn new(model: &dot_vox::Model) -> Self {
+        Self::from_voxels(
+            [model.size.x, model.size.y, model.size.z],
+            model
+                .voxels
+                .iter()
+                .map(|voxel| [voxel.x, voxel.y, voxel.z, voxel.i])
+                .collect(),
+        )
+    }
+
+    fn from_voxels(size: [u32; 3], mut voxels: Vec<[u8; 4]>) -> Self {
+        voxels.sort_unstable();
+        Self { size, voxels }
+    }
+
+    /// The model turned by `rotation`, moved back so no coordinate is negative.
+    ///
+    /// Returns the key and how far every rotated voxel was moved.
+    fn rotated(&self, rotation: VoxRotation) -> (Self, IVec3) {
+        let size = IVec3::from_array(self.size.map(|axis| axis as i32));
+        let far_corner = rotation.transform_ivec3(size - IVec3::ONE);
+        let shift = (-far_corner).max(IVec3::ZERO);
+
+        let voxels = self
+            .voxels
+            .iter()
+            .map(|[x, y, z, i]| {
+                let position =
+                    rotation.transform_ivec3(IVec3::new(*x as i32, *y as i32, *z as i32));
+                let position = (position + shift).as_uvec3();
+                [position.x as u8, position.y as u8, position.z as u8, *i]
+            })
+            .collect();
+        let size = rotation.transform_ivec3(size).abs().as_uvec3().to_array();
+
+        (Self::from_voxels(size, voxels), shift)
+    }
+}
+
+/// A model that is already stored, placed so it looks like the model that was looked up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModelInstance {
+    pub model_id: u32,
+    /// Rotation of the stored model in magicavoxel axes, can be a mirror.
+    pub rotation: VoxRotation,
+    /// Moves the stored model so its voxels land where the voxels of the looked up model would,
+    /// in magicavoxel axes.
+    pub offset: IVec3,
+}
+
+/// Every model of a scene by content, so identical models are only written once.
+#[derive(Debug, Clone, Default)]
+pub struct ModelLibrary {
+    models: HashMap<ModelKey, u32>,
+}
+
+impl ModelLibrary {
+    /// Index of a stored model with exactly the same voxels.
+    pub fn find(&self, model: &dot_vox::Model) -> Option<u32> {
+        self.models.get(&ModelKey::new(model)).copied()
+    }
+
+    /// A stored model that looks like `model` when turned or mirrored by one of the 48 rotations
+    /// a transform can store. An exact match is always preferred.
+    pub fn find_instance(&self, model: &dot_vox::Model) -> Option<ModelInstance> {
+        let key = ModelKey::new(model);
+        let size = IVec3::from_array(key.size.map(|axis| axis as i32));
+
+        VoxRotation::all().find_map(|rotation| {
+            let (rotated, shift) = key.rotated(rotation);
+            let model_id = *self.models.get(&rotated)?;
+
+            // the stored model is `rotation * model + shift`, so the model we want is the stored
+            // one turned back and moved by `-inverse * shift`
+            let inverse = rotation.inverse();
+            let stored_size = IVec3::from_array(rotated.size.map(|axis| axis as i32));
+            // magicavoxel turns a model around the voxel at half its size and puts that voxel on
+            // the translation, correct for the pivots of both models not lining up
+            let offset = inverse.transform_ivec3(stored_size / 2 - shift) - size / 2;
+
+            Some(ModelInstance {
+                model_id,
+                rotation: inverse,
+                offset,
+            })
+        })
+    }
+
+    pub fn insert(&mut self, model: &dot_vox::Model, model_id: u32) {
+        self.models.entry(ModelKey::new(model)).or_insert(model_id);
+    }
+}
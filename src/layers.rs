@@ -0,0 +1,137 @@
+use crate::rig::Skeleton;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+impl Side {
+    /// Guesses the side of a bone from names like `LeftArm`, `upperarm_l` or `Hand.R`.
+    pub fn of(name: &str) -> Option<Self> {
+        let name = name.to_ascii_lowercase();
+        let has_marker = |word: &str, letter: char| {
+            name.contains(word)
+                || ['_', '.', '-', ' ', ':'].iter().any(|separator| {
+                    name.ends_with(&format!("{}{}", separator, letter))
+                        || name.starts_with(&format!("{}{}", letter, separator))
+                })
+        };
+
+        if has_marker("left", 'l') {
+            Some(Side::Left)
+        } else if has_marker("right", 'r') {
+            Some(Side::Right)
+        } else {
+            None
+        }
+    }
+}
+
+/// A named MagicaVoxel layer and the bones that go on it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayerRule {
+    pub name: String,
+    /// A bone matches when its name contains any of these, ignoring case.
+    pub patterns: Vec<String>,
+    /// Only bones on this side match, taken from a name starting with `left` or `right`.
+    pub side: Option<Side>,
+}
+
+impl LayerRule {
+    pub fn new(name: &str, patterns: &[&str]) -> Self {
+        let lowercase = name.to_ascii_lowercase();
+        let side = if lowercase.starts_with("left") {
+            Some(Side::Left)
+        } else if lowercase.starts_with("right") {
+            Some(Side::Right)
+        } else {
+            None
+        };
+
+        Self {
+            name: name.to_string(),
+            patterns: patterns
+                .iter()
+                .map(|pattern| pattern.to_ascii_lowercase())
+                .collect(),
+            side,
+        }
+    }
+
+    /// Parses `name=pattern,pattern` as given on the command line.
+    pub fn parse(value: &str) -> Option<Self> {
+        let (name, patterns) = value.split_once('=')?;
+        let patterns = patterns
+            .split(',')
+            .map(str::trim)
+            .filter(|pattern| !pattern.is_empty())
+            .collect::<Vec<_>>();
+        if name.trim().is_empty() || patterns.is_empty() {
+            return None;
+        }
+        Some(Self::new(name.trim(), &patterns))
+    }
+
+    pub fn matches(&self, bone_name: &str) -> bool {
+        if self.side.is_some() && Side::of(bone_name) != self.side {
+            return false;
+        }
+        let bone_name = bone_name.to_ascii_lowercase();
+        self.patterns
+            .iter()
+            .any(|pattern| bone_name.contains(pattern.as_str()))
+    }
+}
+
+impl std::fmt::Display for LayerRule {
+    /// The same `name=pattern,pattern` that `parse` reads.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}={}", self.name, self.patterns.join(","))
+    }
+}
+
+/// Head, torso, arms and legs, matching the usual humanoid bone names.
+pub fn default_layers() -> Vec<LayerRule> {
+    const ARM: &[&str] = &[
+        "shoulder", "clavicle", "arm", "elbow", "wrist", "hand", "finger", "thumb", "index",
+        "middle", "ring", "pinky",
+    ];
+    const LEG: &[&str] = &[
+        "hip_", "upleg", "thigh", "leg", "knee", "calf", "shin", "ankle", "foot", "toe",
+    ];
+
+    vec![
+        LayerRule::new("head", &["head", "neck", "jaw", "eye"]),
+        LayerRule::new("left arm", ARM),
+        LayerRule::new("right arm", ARM),
+        LayerRule::new("left leg", LEG),
+        LayerRule::new("right leg", LEG),
+        LayerRule::new(
+            "torso",
+            &["spine", "chest", "hips", "pelvis", "torso", "body", "root"],
+        ),
+    ]
+}
+
+/// Name of the layer of every bone.
+///
+/// A layer in `overrides` wins, then the first rule that matches the name of a bone. Bones that
+/// match nothing end up on the layer of their parent, or on no layer at all when they're a root.
+pub fn assign_layers(
+    skeleton: &Skeleton,
+    rules: &[LayerRule],
+    overrides: &[Option<String>],
+) -> Vec<Option<String>> {
+    let mut layers: Vec<Option<String>> = Vec::with_capacity(skeleton.bones.len());
+    for (bone, layer) in skeleton.bones.iter().zip(overrides) {
+        let name = bone.display_name();
+        let matched = rules
+            .iter()
+            .find(|rule| rule.matches(&name))
+            .map(|rule| rule.name.clone());
+        let inherited = bone.parent.and_then(|parent| layers[parent].clone());
+        layers.push(layer.clone().or(matched).or(inherited));
+    }
+    layers
+}
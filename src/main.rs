@@ -1,27 +1,592 @@
-use std::{fs::File, io::BufWriter};
-
-use bevy::{
-    math::bounding::{Aabb3d, BoundingVolume},
-    prelude::*,
-    window::WindowResolution,
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
 };
+
+use bevy::{prelude::*, window::WindowResolution};
 use bevy_flycam::prelude::*;
 use bevy_inspector_egui::quick::WorldInspectorPlugin;
 use dot_vox::DotVoxData;
-use gltf::{buffer, image, Document};
-use slab::Slab;
-
-#[derive(Debug)]
-struct VoxelObject {
-    name: String,
-    aabb: Aabb3d,
-    children: Vec<usize>,
-}
+use gltf::Document;
+
+use animation::{AnimationSampler, ClipRef, ClipSettings, LoopMode};
+use config::{Config, ConfigFormat, FileSettings, Settings};
+use coords::{Axis, Basis, CoordinateSystem, Handedness};
+use error::{Error, Result};
+use filter::BonePattern;
+use humanoid::{BoneMap, HumanBone, PresetChoice};
+use instancing::{ModelInstance, ModelLibrary};
+use layers::{LayerRule, Side};
+use mesh::{MeshFormat, MeshSettings};
+use rig::{RigSettings, Skeleton};
+use root_motion::RootMotion;
+use rotation::VoxRotation;
+use skinning::SkinnedMesh;
+use tiles::{Tile, VoxelModel};
+use vox_tree::{TransformFrame, VoxGroup, VoxNode, VoxShape, VoxTransform};
+
+mod animation;
+mod bake;
+mod batch;
+mod config;
+mod coords;
+mod error;
+mod filter;
+mod frames;
+mod gltf_writer;
+mod humanoid;
+mod instancing;
+mod layers;
+mod mesh;
+mod obj;
+mod preview;
+mod qubicle;
+mod retarget;
+mod rig;
+mod root_motion;
+mod rotation;
+mod skinned_gltf;
+mod skinning;
+mod symmetry;
+mod tiles;
+mod vox_gltf;
+mod vox_tree;
+mod voxelize;
 
 // Thickness of the bone voxel, whatever axis is not the longest will be this thick
 const BONE_VOXEL_THICKNESS: f32 = 10.0;
 
+#[derive(Resource, Debug, Clone)]
+struct Options {
+    input: String,
+    output: String,
+    // only print the clips of the input and exit
+    list_clips: bool,
+    // print the transforms of an existing .vox file and exit
+    inspect: Option<String>,
+    // convert an existing .vox file to a glTF and exit
+    to_gltf: Option<String>,
+    // convert every gltf in this folder or glob instead of `input`
+    batch: Option<String>,
+    // where batch outputs go, mirroring the folders of the inputs
+    out_dir: String,
+    // number of files converted at once in batch mode, defaults to one per core
+    jobs: Option<usize>,
+    // merge the converted rig into this existing .vox file instead of writing it on its own
+    merge_into: Option<String>,
+    // names of nodes to hide in the output
+    hide: Vec<String>,
+    // write every frame to its own file next to the output, plus a manifest
+    per_frame: bool,
+    // print the settings every input would be converted with and exit
+    dump_config: Option<ConfigFormat>,
+    config: Config,
+    // settings given on the command line, these win over the config file
+    overrides: Settings,
+
+    // everything below is filled in from the settings by `for_input`
+    // voxelize the skinned mesh per frame instead of exporting rigid bone parts
+    bake: bool,
+    // name or index of the skin, defaults to the first one
+    skin: Option<String>,
+    // colors put at the start of the palette
+    palette: Vec<[u8; 4]>,
+    root_motion: RootMotion,
+    // bone that carries the root motion, defaults to the root of the skeleton
+    root_bone: Option<String>,
+    clip: ClipSettings,
+    rig: RigSettings,
+    // axes of the input, converted to the z-up axes of magicavoxel on export
+    coordinates: CoordinateSystem,
+    // maps the bones of the input onto the canonical humanoid skeleton
+    bone_map: BoneMap,
+    // take the clip from this rig and retarget it onto the input
+    animation_from: Option<String>,
+    source_bone_map: BoneMap,
+    // also write the models as meshes next to the output
+    mesh_format: Option<MeshFormat>,
+    ambient_occlusion: bool,
+    // write the glTF mesh as one mesh skinned to the original skeleton
+    skinned_mesh: bool,
+    // also write a .qb next to every .vox
+    qubicle: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            input: "./input/character.glb".to_string(),
+            output: "output.vox".to_string(),
+            list_clips: false,
+            inspect: None,
+            to_gltf: None,
+            batch: None,
+            out_dir: "output".to_string(),
+            jobs: None,
+            merge_into: None,
+            hide: vec![],
+            per_frame: false,
+            dump_config: None,
+            config: Config::default(),
+            overrides: Settings::default(),
+            bake: false,
+            skin: None,
+            palette: vec![],
+            root_motion: RootMotion::Keep,
+            root_bone: None,
+            clip: ClipSettings::default(),
+            rig: RigSettings::default(),
+            coordinates: CoordinateSystem::default(),
+            bone_map: BoneMap::default(),
+            animation_from: None,
+            source_bone_map: BoneMap::default(),
+            mesh_format: None,
+            ambient_occlusion: false,
+            skinned_mesh: false,
+            qubicle: false,
+        }
+    }
+}
+
+impl Options {
+    fn from_args() -> Self {
+        let mut options = Options::default();
+        let mut args = std::env::args().skip(1);
+
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .unwrap_or_else(|| usage_error(&format!("{} expects a value", name)))
+            };
+            let overrides = &mut options.overrides;
+
+            match arg.as_str() {
+                "--list-clips" => options.list_clips = true,
+                "--inspect" => options.inspect = Some(value(&arg)),
+                "--to-gltf" => options.to_gltf = Some(value(&arg)),
+                "--batch" => options.batch = Some(value(&arg)),
+                "--out-dir" => options.out_dir = value(&arg),
+                "--jobs" | "-j" => {
+                    let jobs = value(&arg);
+                    options.jobs = match jobs.parse() {
+                        Ok(0) | Err(_) => usage_error(&format!(
+                            "{} expects a number of threads, got {:?}",
+                            arg, jobs
+                        )),
+                        Ok(jobs) => Some(jobs),
+                    };
+                }
+                "--config" => {
+                    options.config =
+                        Config::load(value(&arg)).unwrap_or_else(|error| exit_with_error(&error));
+                }
+                "--dump-config" => {
+                    let format = value(&arg);
+                    options.dump_config = Some(ConfigFormat::parse(&format).unwrap_or_else(|| {
+                        usage_error(&format!("unknown config format {:?}", format))
+                    }));
+                }
+                "--merge-into" => options.merge_into = Some(value(&arg)),
+                "--hide" => options.hide.push(value(&arg)),
+                "--bake" => overrides.bake = Some(true),
+                "--per-frame" => options.per_frame = true,
+                "--output" | "-o" => options.output = value(&arg),
+                "--skin" => overrides.skin = Some(value(&arg)),
+                "--clip" => overrides.clip = Some(value(&arg)),
+                "--start" => overrides.start = Some(parse_seconds(&arg, &value(&arg))),
+                "--end" => overrides.end = Some(parse_seconds(&arg, &value(&arg))),
+                "--fps" => {
+                    let fps = parse_number(&arg, &value(&arg));
+                    if !(fps.is_finite() && fps > 0.0) {
+                        usage_error(&format!("--fps expects a positive number, got {}", fps));
+                    }
+                    overrides.fps = Some(fps);
+                }
+                "--scale" => overrides.scale = Some(parse_number(&arg, &value(&arg))),
+                "--thickness" => overrides.thickness = Some(parse_number(&arg, &value(&arg))),
+                "--root-motion" => {
+                    let mode = value(&arg);
+                    overrides.root_motion = Some(RootMotion::parse(&mode).unwrap_or_else(|| {
+                        usage_error(&format!("unknown root motion mode {:?}", mode))
+                    }));
+                }
+                "--root-bone" => overrides.root_bone = Some(value(&arg)),
+                "--layer" => {
+                    let rule = value(&arg);
+                    if LayerRule::parse(&rule).is_none() {
+                        usage_error(&format!("--layer expects name=pattern,..., got {:?}", rule));
+                    }
+                    // the first custom layer replaces the defaults
+                    overrides.layers.get_or_insert_with(Vec::new).push(rule);
+                }
+                "--no-layers" => overrides.layers = Some(vec![]),
+                "--include" => overrides
+                    .include
+                    .get_or_insert_with(Vec::new)
+                    .push(value(&arg)),
+                "--exclude" => overrides
+                    .exclude
+                    .get_or_insert_with(Vec::new)
+                    .push(value(&arg)),
+                "--collapse" => overrides.collapse = Some(true),
+                "--mirror" => overrides.mirror = Some(value(&arg)),
+                "--mesh" => overrides.mesh = Some(value(&arg)),
+                "--ambient-occlusion" => overrides.ambient_occlusion = Some(true),
+                "--skinned-mesh" => overrides.skinned_mesh = Some(true),
+                "--qubicle" => overrides.qubicle = Some(true),
+                "--bone-preset" => overrides.bone_preset = Some(value(&arg)),
+                "--animation-from" => overrides.animation_from = Some(value(&arg)),
+                "--source-bone-preset" => overrides.source_bone_preset = Some(value(&arg)),
+                "--up" => overrides.up = Some(parse_axis(&arg, &value(&arg))),
+                "--forward" => overrides.forward = Some(parse_axis(&arg, &value(&arg))),
+                "--left-handed" => overrides.handedness = Some(Handedness::Left),
+                "--loop" => {
+                    let mode = value(&arg);
+                    overrides.loop_mode =
+                        Some(LoopMode::parse(&mode).unwrap_or_else(|| {
+                            usage_error(&format!("unknown loop mode {:?}", mode))
+                        }));
+                }
+                _ if arg.starts_with('-') => usage_error(&format!("unknown option {}", arg)),
+                _ => options.input = arg,
+            }
+        }
+
+        options
+    }
+
+    /// Built-in defaults, then the config file, then the command line.
+    fn effective_settings(&self, input: &Path) -> Result<Settings> {
+        let mut settings = Settings::defaults();
+        settings.merge(&self.config.settings_for(input)?);
+        settings.merge(&self.overrides);
+        Ok(settings)
+    }
+
+    /// The options to convert `input` with.
+    fn for_input(&self, input: &str) -> Result<Options> {
+        let mut options = Options {
+            input: input.to_string(),
+            ..self.clone()
+        };
+        options.apply(&self.effective_settings(Path::new(input))?)?;
+        Ok(options)
+    }
+
+    fn apply(&mut self, settings: &Settings) -> Result<()> {
+        self.skin.clone_from(&settings.skin);
+        self.clip.clip = settings.clip.as_deref().map(ClipRef::parse);
+        for (name, seconds) in [("start", settings.start), ("end", settings.end)] {
+            if let Some(seconds) = seconds.filter(|seconds| !seconds.is_finite()) {
+                return Err(Error::Config(format!(
+                    "{} expects a finite number of seconds, got {}",
+                    name, seconds
+                )));
+            }
+        }
+        self.clip.start = settings.start;
+        self.clip.end = settings.end;
+        self.root_bone.clone_from(&settings.root_bone);
+        if let Some(fps) = settings.fps {
+            if !(fps.is_finite() && fps > 0.0) {
+                return Err(Error::Config(format!(
+                    "fps expects a positive number, got {}",
+                    fps
+                )));
+            }
+            self.clip.fps = fps;
+        }
+        if let Some(loop_mode) = settings.loop_mode {
+            self.clip.loop_mode = loop_mode;
+        }
+        if let Some(root_motion) = settings.root_motion {
+            self.root_motion = root_motion;
+        }
+        if let Some(bake) = settings.bake {
+            self.bake = bake;
+        }
+        if let Some(scale) = settings.scale {
+            self.rig.scale = scale;
+        }
+        if let Some(thickness) = settings.thickness {
+            self.rig.thickness = thickness;
+        }
+        if let Some(color) = settings.color {
+            self.rig.color = color.0;
+        }
+        if let Some(palette) = &settings.palette {
+            self.palette = palette.iter().map(|color| color.0).collect();
+        }
+        if let Some(layers) = &settings.layers {
+            self.rig.layers = layers
+                .iter()
+                .map(|rule| {
+                    LayerRule::parse(rule).ok_or_else(|| {
+                        Error::Config(format!("layer {:?} isn't name=pattern,...", rule))
+                    })
+                })
+                .collect::<Result<_>>()?;
+        }
+        self.rig.bones = settings
+            .bones
+            .iter()
+            .map(|(pattern, bone)| Ok((parse_bone_pattern(pattern)?, bone.clone())))
+            .collect::<Result<_>>()?;
+        if let Some(include) = &settings.include {
+            self.rig.filter.include = include
+                .iter()
+                .map(|pattern| parse_bone_pattern(pattern))
+                .collect::<Result<_>>()?;
+        }
+        if let Some(exclude) = &settings.exclude {
+            self.rig.filter.exclude = exclude
+                .iter()
+                .map(|pattern| parse_bone_pattern(pattern))
+                .collect::<Result<_>>()?;
+        }
+        if let Some(collapse) = settings.collapse {
+            self.rig.filter.collapse = collapse;
+        }
+        if let Some(mirror) = &settings.mirror {
+            self.rig.mirror = match mirror.to_ascii_lowercase().as_str() {
+                "none" => None,
+                "left" => Some(Side::Left),
+                "right" => Some(Side::Right),
+                _ => {
+                    return Err(Error::Config(format!(
+                        "mirror expects left, right or none, got {:?}",
+                        mirror
+                    )))
+                }
+            };
+        }
+        if let Some(mesh) = &settings.mesh {
+            self.mesh_format = match mesh.to_ascii_lowercase().as_str() {
+                "none" => None,
+                _ => Some(MeshFormat::parse(mesh).ok_or_else(|| {
+                    Error::Config(format!(
+                        "mesh expects obj, gltf, glb or none, got {:?}",
+                        mesh
+                    ))
+                })?),
+            };
+        }
+        if let Some(ambient_occlusion) = settings.ambient_occlusion {
+            self.ambient_occlusion = ambient_occlusion;
+        }
+        if let Some(skinned_mesh) = settings.skinned_mesh {
+            self.skinned_mesh = skinned_mesh;
+        }
+        if let Some(qubicle) = settings.qubicle {
+            self.qubicle = qubicle;
+        }
+        if self.skinned_mesh
+            && !matches!(self.mesh_format, Some(MeshFormat::Gltf | MeshFormat::Glb))
+        {
+            return Err(Error::Config(
+                "skinned_mesh needs mesh gltf or glb".to_string(),
+            ));
+        }
+        if let Some(preset) = &settings.bone_preset {
+            self.bone_map.preset = parse_bone_preset(preset)?;
+        }
+        self.bone_map.custom = parse_bone_map(&settings.bone_map)?;
+        self.animation_from.clone_from(&settings.animation_from);
+        if let Some(preset) = &settings.source_bone_preset {
+            self.source_bone_map.preset = parse_bone_preset(preset)?;
+        }
+        self.source_bone_map.custom = parse_bone_map(&settings.source_bone_map)?;
+
+        if let Some(up) = settings.up {
+            self.coordinates.up = up;
+        }
+        if let Some(forward) = settings.forward {
+            self.coordinates.forward = forward;
+        }
+        if let Some(handedness) = settings.handedness {
+            self.coordinates.handedness = handedness;
+        }
+        if Basis::new(self.coordinates).is_none() {
+            return Err(Error::Config(
+                "up and forward can't be on the same axis".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn basis(&self) -> Basis {
+        Basis::new(self.coordinates).unwrap()
+    }
+
+    fn mesh_settings(&self) -> MeshSettings {
+        MeshSettings {
+            basis: self.basis(),
+            scale: self.rig.scale,
+            ambient_occlusion: self.ambient_occlusion,
+        }
+    }
+}
+
+fn parse_bone_pattern(pattern: &str) -> Result<BonePattern> {
+    BonePattern::parse(pattern).map_err(|error| Error::Config(format!("bone pattern {}", error)))
+}
+
+fn parse_bone_preset(preset: &str) -> Result<PresetChoice> {
+    PresetChoice::parse(preset)
+        .ok_or_else(|| Error::Config(format!("unknown bone preset {:?}", preset)))
+}
+
+fn parse_bone_map(map: &BTreeMap<String, String>) -> Result<Vec<(String, HumanBone)>> {
+    map.iter()
+        .map(|(name, human)| {
+            let human = HumanBone::parse(human).ok_or_else(|| {
+                Error::Config(format!(
+                    "{:?} isn't a humanoid bone like left_upper_arm",
+                    human
+                ))
+            })?;
+            Ok((name.clone(), human))
+        })
+        .collect()
+}
+
+fn parse_axis(name: &str, value: &str) -> Axis {
+    Axis::parse(value).unwrap_or_else(|| {
+        usage_error(&format!(
+            "{} expects an axis like +y or -z, got {:?}",
+            name, value
+        ))
+    })
+}
+
+fn parse_number(name: &str, value: &str) -> f32 {
+    value
+        .parse()
+        .unwrap_or_else(|_| usage_error(&format!("{} expects a number, got {:?}", name, value)))
+}
+
+fn parse_seconds(name: &str, value: &str) -> f32 {
+    let seconds = parse_number(name, value);
+    if !seconds.is_finite() {
+        usage_error(&format!(
+            "{} expects a finite number of seconds, got {}",
+            name, seconds
+        ));
+    }
+    seconds
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("error: {}", message);
+    eprintln!(
+        "usage: skeleton-to-vox [input] [-o output] [--list-clips] [--inspect file.vox] [--to-gltf file.vox] [--batch folder|glob] [--out-dir folder] [--jobs n] [--config file.toml|file.ron] [--dump-config toml|ron] [--merge-into file.vox] [--hide node] [--bake] [--per-frame] [--skin name|index] [--clip name|index] \
+         [--start seconds] [--end seconds] [--fps fps] [--loop once|loop|pingpong] \
+         [--scale voxels-per-unit] [--thickness voxels] [--root-motion keep|in-place|extract] \
+         [--root-bone name] [--layer name=pattern,...] [--no-layers] [--include bones] [--exclude bones] [--collapse] [--mirror left|right|none] [--mesh obj|gltf|glb|none] [--ambient-occlusion] [--skinned-mesh] [--qubicle] [--bone-preset none|auto|mixamo|unreal|rigify] [--animation-from file.glb] [--source-bone-preset preset] [--up axis] [--forward axis] [--left-handed]"
+    );
+    std::process::exit(2);
+}
+
+fn exit_with_error(error: &Error) -> ! {
+    eprintln!("error: {}", error);
+    std::process::exit(1);
+}
+
+fn dump_config(config: &Config, format: ConfigFormat) {
+    match config.to_string(format) {
+        Ok(text) => print!("{}", text),
+        Err(error) => exit_with_error(&error),
+    }
+}
+
 fn main() {
+    let options = Options::from_args();
+
+    if options.list_clips {
+        match gltf::import(&options.input) {
+            Ok((document, buffers, _images)) => animation::print_clips(&document, &buffers),
+            Err(error) => exit_with_error(&Error::Gltf(error)),
+        }
+        return;
+    }
+
+    if let Some(path) = &options.inspect {
+        if let Err(error) = inspect_vox(path) {
+            exit_with_error(&error);
+        }
+        return;
+    }
+
+    if let Some(path) = &options.to_gltf {
+        if let Err(error) = vox_to_gltf(&options, path) {
+            exit_with_error(&error);
+        }
+        return;
+    }
+
+    if let Some(pattern) = &options.batch {
+        let (root, inputs) =
+            batch::find_inputs(pattern).unwrap_or_else(|error| exit_with_error(&error));
+        if inputs.is_empty() {
+            exit_with_error(&Error::Unsupported(format!(
+                "no .gltf or .glb files in {}",
+                pattern
+            )));
+        }
+        if let Some(format) = options.dump_config {
+            let files = inputs
+                .iter()
+                .map(|input| {
+                    Ok(FileSettings {
+                        pattern: glob::Pattern::escape(&input.to_string_lossy()),
+                        settings: options.effective_settings(input)?,
+                    })
+                })
+                .collect::<Result<_>>();
+            let config = Config {
+                defaults: Settings::default(),
+                files: files.unwrap_or_else(|error| exit_with_error(&error)),
+            };
+            dump_config(&config, format);
+            return;
+        }
+
+        let results = batch::run(
+            &options,
+            &root,
+            &inputs,
+            Path::new(&options.out_dir),
+            options.jobs,
+        )
+        .unwrap_or_else(|error| exit_with_error(&error));
+        batch::print_summary(&results);
+        if results
+            .iter()
+            .any(|result| matches!(result.outcome, batch::Outcome::Failed(_)))
+        {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(format) = options.dump_config {
+        let config = Config {
+            defaults: options
+                .effective_settings(Path::new(&options.input))
+                .unwrap_or_else(|error| exit_with_error(&error)),
+            files: vec![],
+        };
+        dump_config(&config, format);
+        return;
+    }
+
+    let options = options
+        .for_input(&options.input)
+        .unwrap_or_else(|error| exit_with_error(&error));
+
     App::new()
         // window size 640x480
         .add_plugins(DefaultPlugins.set(WindowPlugin {
@@ -38,6 +603,7 @@ fn main() {
         })
         .add_plugins(WorldInspectorPlugin::new())
         .add_plugins(NoCameraPlayerPlugin)
+        .insert_resource(options)
         .add_systems(Startup, setup_camera)
         .add_systems(Startup, convert)
         .run();
@@ -50,10 +616,20 @@ fn main() {
 
 struct VoxScene {
     data: DotVoxData,
+    // number of palette entries handed out by `color_index`
+    used_colors: usize,
+    // everything passed in is in the axes of the source, this turns it into magicavoxel axes
+    basis: Basis,
+    // the scene graph, only flattened into `data.scenes` when saving
+    root: VoxNode,
+    models_by_content: ModelLibrary,
+    // every `_name` in the scene, to keep them unique
+    names: HashSet<String>,
 }
 
 impl VoxScene {
-    pub fn new() -> Self {
+    /// An empty scene, positions and rotations passed in are converted with `basis`.
+    pub fn new(basis: Basis) -> Self {
         Self {
             data: DotVoxData {
                 layers: vec![dot_vox::Layer {
@@ -76,558 +652,860 @@ impl VoxScene {
                     };
                     256
                 ],
-                scenes: vec![
-                    dot_vox::SceneNode::Transform {
-                        attributes: Default::default(),
-                        child: 1,
-                        layer_id: 4294967295,
-                        frames: vec![dot_vox::Frame {
-                            attributes: Default::default(),
-                        }],
-                    },
-                    dot_vox::SceneNode::Group {
-                        attributes: Default::default(),
-                        children: vec![],
-                    },
-                ],
+                // filled from `root` when saving
+                scenes: vec![],
                 version: 150,
             },
+            root: VoxNode::Transform(VoxTransform {
+                attributes: Default::default(),
+                frames: vec![TransformFrame::default()],
+                layer_id: 4294967295,
+                child: Box::new(VoxNode::Group(VoxGroup {
+                    attributes: Default::default(),
+                    children: vec![],
+                })),
+            }),
+            used_colors: 0,
+            basis,
+            models_by_content: ModelLibrary::default(),
+            names: HashSet::new(),
         }
     }
 
-    pub fn save(&self, path: &str) {
-        let mut vox_file = BufWriter::new(File::create(path).unwrap());
-        println!("{:#?}", self.data.scenes);
-        self.data.write_vox(&mut vox_file).unwrap();
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let mut scenes = vec![];
+        self.root.flatten(&mut scenes);
+        let data = DotVoxData {
+            scenes,
+            ..self.data.clone()
+        };
+
+        let io_error = |error| Error::Io(path.to_path_buf(), error);
+        let mut vox_file = BufWriter::new(File::create(path).map_err(io_error)?);
+        data.write_vox(&mut vox_file).map_err(io_error)?;
+        vox_file.flush().map_err(io_error)
     }
 
-    pub fn add_to_root(&mut self, i: u32) {
-        self.data.scenes[1] = dot_vox::SceneNode::Group {
-            attributes: Default::default(),
-            children: vec![i],
+    /// Loads an existing `.vox` file, e.g. to edit it or to `merge` a converted rig into it.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let invalid = |error: &str| Error::InvalidVox(format!("{}: {}", path.display(), error));
+        let mut data = dot_vox::load(&path.to_string_lossy()).map_err(invalid)?;
+
+        let mut scene = Self::new(Basis::default());
+        if !data.scenes.is_empty() {
+            scene.root = VoxNode::from_scenes(&data.scenes, 0, data.models.len())
+                .map_err(|error| invalid(&error))?;
+        }
+        scene.data.version = data.version;
+        scene.data.layers = std::mem::take(&mut data.layers);
+        scene.data.materials = std::mem::take(&mut data.materials);
+        let colors = data.palette.len().min(256);
+        scene.data.palette[..colors].copy_from_slice(&data.palette[..colors]);
+
+        // new colors go after the ones the models use
+        scene.used_colors = data
+            .models
+            .iter()
+            .flat_map(|model| &model.voxels)
+            .map(|voxel| voxel.i as usize + 1)
+            .max()
+            .unwrap_or(0);
+
+        for model in data.models {
+            let model_id = scene.data.models.len() as u32;
+            scene.models_by_content.insert(&model, model_id);
+            scene.data.models.push(model);
+
+            // files without a scene graph just have their models side by side
+            if data.scenes.is_empty() {
+                let shape = scene.shape(None, model_id);
+                let transform = scene.transform(None, &[VoxFrame::default()], shape);
+                scene.add_to_root(transform);
+            }
+        }
+
+        let mut names = HashSet::new();
+        scene.root.walk(&mut |node| {
+            names.extend(node.name().map(str::to_string));
+        });
+        scene.names = names;
+
+        Ok(scene)
+    }
+
+    pub fn root(&self) -> &VoxNode {
+        &self.root
+    }
+
+    pub fn models(&self) -> &[dot_vox::Model] {
+        &self.data.models
+    }
+
+    pub fn palette(&self) -> &[dot_vox::Color] {
+        &self.data.palette
+    }
+
+    /// The root transform of the scene graph.
+    ///
+    /// Names set through this aren't taken into account when making new names unique.
+    pub fn root_mut(&mut self) -> &mut VoxNode {
+        &mut self.root
+    }
+
+    /// Adds the nodes, models, colors and layers of `other` to this scene.
+    ///
+    /// Everything below the root of `other` ends up below the root of this scene, names that
+    /// are already taken get a number appended.
+    pub fn merge(&mut self, other: VoxScene) -> Result<()> {
+        let mut colors = [0u8; 256];
+        let mut used = [false; 256];
+        for voxel in other.data.models.iter().flat_map(|model| &model.voxels) {
+            used[voxel.i as usize] = true;
+        }
+        for (index, color) in other.data.palette.iter().enumerate().take(256) {
+            if used[index] {
+                colors[index] = self.color_index([color.r, color.g, color.b, color.a]);
+            }
+        }
+
+        let models = other
+            .data
+            .models
+            .into_iter()
+            .map(|mut model| {
+                for voxel in &mut model.voxels {
+                    voxel.i = colors[voxel.i as usize];
+                }
+                self.add_model(model)
+            })
+            .collect::<Vec<_>>();
+
+        // named layers are matched by name, the default layer stays the default layer
+        let layers = other
+            .data
+            .layers
+            .iter()
+            .enumerate()
+            .map(|(index, layer)| match layer.attributes.get("_name") {
+                None if index == 0 => 0,
+                Some(name) => self
+                    .data
+                    .layers
+                    .iter()
+                    .position(|existing| existing.attributes.get("_name") == Some(name))
+                    .map_or_else(|| self.add_layer(name), |existing| existing as u32),
+                None => {
+                    self.data.layers.push(layer.clone());
+                    self.data.layers.len() as u32 - 1
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let VoxNode::Transform(root) = other.root else {
+            return Err(Error::InvalidVox(
+                "the root node isn't a transform".to_string(),
+            ));
+        };
+        let nodes = match *root.child {
+            VoxNode::Group(group) => group.children,
+            node => vec![node],
+        };
+        for mut node in nodes {
+            node.walk_mut(&mut |node| {
+                if let Some(name) = node.name().map(str::to_string) {
+                    let unique = self.unique_name(&name);
+                    node.set_attribute("_name", &unique);
+                }
+                match node {
+                    VoxNode::Transform(transform) => {
+                        transform.layer_id = layers
+                            .get(transform.layer_id as usize)
+                            .copied()
+                            .unwrap_or(0);
+                    }
+                    VoxNode::Shape(shape) => {
+                        for model in &mut shape.models {
+                            model.model_id = models[model.model_id as usize];
+                        }
+                    }
+                    VoxNode::Group(_) => {}
+                }
+            });
+
+            self.add_to_root(node);
+        }
+
+        Ok(())
+    }
+
+    /// Adds a node below the root group of the scene.
+    pub fn add_to_root(&mut self, node: VoxNode) {
+        let VoxNode::Transform(root) = &mut self.root else {
+            return;
         };
+        match root.child.as_mut() {
+            VoxNode::Group(group) => group.children.push(node),
+            // a root that points at a single node gets a group to hold both
+            child => {
+                let existing = std::mem::replace(
+                    child,
+                    VoxNode::Group(VoxGroup {
+                        attributes: Default::default(),
+                        children: vec![],
+                    }),
+                );
+                if let VoxNode::Group(group) = child {
+                    group.children = vec![existing, node];
+                }
+            }
+        }
     }
 
-    pub fn add_group(&mut self, transform: Transform, children: Vec<u32>) -> u32 {
-        self.data.scenes.push(dot_vox::SceneNode::Group {
+    /// Names the root transform and group of the scene.
+    pub fn set_root_name(&mut self, name: &str) {
+        let mut root = std::mem::replace(&mut self.root, VoxNode::Group(Default::default()));
+        self.set_name(&mut root, name);
+        if let VoxNode::Transform(transform) = &mut root {
+            self.set_name(&mut transform.child, &format!("{}_group", name));
+        }
+        self.root = root;
+    }
+
+    /// Sets `_name` of a node, a number is appended when another node already has the name.
+    ///
+    /// Returns the name that was used.
+    pub fn set_name(&mut self, node: &mut VoxNode, name: &str) -> String {
+        let unique = self.unique_name(name);
+        node.set_attribute("_name", &unique);
+        unique
+    }
+
+    /// Reserves `name`, or `name` with a number appended when it's already taken.
+    fn unique_name(&mut self, name: &str) -> String {
+        let mut unique = name.to_string();
+        let mut counter = 1;
+        while self.names.contains(&unique) {
+            counter += 1;
+            unique = format!("{}_{}", name, counter);
+        }
+
+        self.names.insert(unique.clone());
+        unique
+    }
+
+    fn named(&mut self, mut node: VoxNode, name: Option<&str>) -> VoxNode {
+        if let Some(name) = name {
+            self.set_name(&mut node, name);
+        }
+        node
+    }
+
+    pub fn group(&mut self, name: Option<&str>, children: Vec<VoxNode>) -> VoxNode {
+        let group = VoxNode::Group(VoxGroup {
             attributes: Default::default(),
             children,
         });
+        self.named(group, name)
+    }
 
-        let (translation, rotation) = Self::transform_to_magica(transform);
-        let mut frame_attributes = dot_vox::Dict::new();
-        frame_attributes.insert("_t".to_string(), translation);
-        // store a row-major rotation in the bits of a byte
-        // we can only represent 90 degree rotations, so we clamp the rotation of our quaternion to 90 degrees
-        // u8
-        frame_attributes.insert("_r".to_string(), rotation);
-
-        let group_idx = self.data.scenes.len() as u32 - 1;
-        self.data.scenes.push(dot_vox::SceneNode::Transform {
+    /// A transform node, with more than one transform every frame becomes a keyframe.
+    pub fn transform(
+        &mut self,
+        name: Option<&str>,
+        frames: &[VoxFrame],
+        child: VoxNode,
+    ) -> VoxNode {
+        let frames = frames
+            .iter()
+            .enumerate()
+            .map(|(i, frame)| TransformFrame {
+                keyframe: (frames.len() > 1).then_some(i as u32),
+                ..self.frame_to_magica(*frame)
+            })
+            .collect();
+
+        let transform = VoxNode::Transform(VoxTransform {
             attributes: Default::default(),
-            frames: vec![dot_vox::Frame {
-                attributes: frame_attributes,
-            }],
-            child: group_idx,
+            frames,
             layer_id: 0,
+            child: Box::new(child),
         });
+        self.named(transform, name)
+    }
 
-        return self.data.scenes.len() as u32 - 1;
+    /// Adds a named layer that nodes can be put on with `VoxNode::set_layer`.
+    pub fn add_layer(&mut self, name: &str) -> u32 {
+        let mut attributes = dot_vox::Dict::new();
+        attributes.insert("_name".to_string(), name.to_string());
+        self.data.layers.push(dot_vox::Layer { attributes });
+        self.data.layers.len() as u32 - 1
     }
 
-    pub fn add_from_aabb(
-        &mut self,
-        name: String,
-        transform: Transform,
-        local_end_position: Vec3,
-    ) -> u32 {
-        self.data.models.push(dot_vox::Model {
-            size: dot_vox::Size {
-                x: 1,
-                y: 1,
-                z: local_end_position.y as u32,
-            },
-            voxels: vec![],
-        });
+    pub fn shape(&mut self, name: Option<&str>, model_id: u32) -> VoxNode {
+        self.animated_shape(name, &[model_id])
+    }
 
-        self.data.scenes.push(dot_vox::SceneNode::Shape {
+    /// Shape with one model per frame, keyed by `_f` when there is more than one.
+    pub fn animated_shape(&mut self, name: Option<&str>, model_ids: &[u32]) -> VoxNode {
+        let models = model_ids
+            .iter()
+            .enumerate()
+            .map(|(i, model_id)| {
+                let mut attributes = dot_vox::Dict::new();
+                if model_ids.len() > 1 {
+                    attributes.insert("_f".to_string(), i.to_string());
+                }
+                dot_vox::ShapeModel {
+                    model_id: *model_id,
+                    attributes,
+                }
+            })
+            .collect();
+
+        let shape = VoxNode::Shape(VoxShape {
             attributes: Default::default(),
-            models: vec![dot_vox::ShapeModel {
-                model_id: (self.data.models.len() - 1) as u32,
-                attributes: Default::default(),
-            }],
+            models,
         });
+        self.named(shape, name)
+    }
 
-        let (translation, rotation) = Self::transform_to_magica(transform);
-        let mut frame_attributes = dot_vox::Dict::new();
-        frame_attributes.insert("_t".to_string(), translation);
-        // store a row-major rotation in the bits of a byte
-        // we can only represent 90 degree rotations, so we clamp the rotation of our quaternion to 90 degrees
-        // u8
-        frame_attributes.insert("_r".to_string(), rotation);
-
-        let mut transform_attributes = dot_vox::Dict::new();
-        transform_attributes.insert("_name".to_string(), name);
-
-        let vox_transform = dot_vox::SceneNode::Transform {
-            attributes: transform_attributes,
-            frames: vec![dot_vox::Frame {
-                attributes: frame_attributes,
-            }],
-            child: self.data.scenes.len() as u32 - 1,
-            layer_id: 0,
+    /// Palette index for `color`, new colors take the next free palette entry. Once the palette
+    /// is full the closest existing color is used.
+    pub fn color_index(&mut self, color: [u8; 4]) -> u8 {
+        let [r, g, b, a] = color;
+        let used = &self.data.palette[..self.used_colors];
+        if let Some(index) = used
+            .iter()
+            .position(|c| c.r == r && c.g == g && c.b == b && c.a == a)
+        {
+            return index as u8;
+        }
+
+        // index 255 can't be written, voxels store the palette index plus one
+        if self.used_colors < 255 {
+            self.data.palette[self.used_colors] = dot_vox::Color { r, g, b, a };
+            self.used_colors += 1;
+            return (self.used_colors - 1) as u8;
+        }
+
+        let distance = |c: &dot_vox::Color| {
+            let d = |x: u8, y: u8| (x as i32 - y as i32).pow(2);
+            d(c.r, r) + d(c.g, g) + d(c.b, b) + d(c.a, a)
         };
+        used.iter()
+            .enumerate()
+            .min_by_key(|(_, c)| distance(c))
+            .map_or(0, |(index, _)| index as u8)
+    }
 
-        self.data.scenes.push(vox_transform);
+    /// Adds a model, or returns the index of an identical one that was added before.
+    pub fn add_model(&mut self, model: dot_vox::Model) -> u32 {
+        if let Some(model_id) = self.models_by_content.find(&model) {
+            return model_id;
+        }
 
-        return self.data.scenes.len() as u32 - 1;
+        let model_id = self.data.models.len() as u32;
+        self.models_by_content.insert(&model, model_id);
+        self.data.models.push(model);
+        model_id
     }
 
-    fn transform_to_magica(transform: Transform) -> (String, String) {
-        let translation = format!(
-            "{} {} {}",
-            transform.translation.x as i32,
-            transform.translation.z as i32,
-            transform.translation.y as i32
-        );
+    /// A transform with a shape showing `model`.
+    ///
+    /// When a stored model looks the same after turning or mirroring it, that one is reused
+    /// and the transform does the turning. Models too large for magicavoxel are split into
+    /// tiles, grouped under the returned transform.
+    pub fn model_instance(&mut self, name: Option<&str>, model: VoxelModel) -> VoxNode {
+        let mut tiles = model.tiles();
+        if tiles.len() == 1 {
+            let tile = tiles.pop().unwrap();
+            return self.tile_instance(name, tile);
+        }
 
-        let rotation = Self::quat_to_u8(Self::snap_quat(transform.rotation)).to_string();
+        let children = tiles
+            .into_iter()
+            .enumerate()
+            .filter(|(_, tile)| !tile.model.voxels.is_empty())
+            .map(|(i, tile)| {
+                let tile_name = name.map(|name| format!("{}_tile{}", name, i));
+                self.tile_instance(tile_name.as_deref(), tile)
+            })
+            .collect();
+        let group_name = name.map(|name| format!("{}_group", name));
+        let group = self.group(group_name.as_deref(), children);
+        self.transform(name, &[VoxFrame::default()], group)
+    }
 
-        (translation, rotation)
+    fn tile_instance(&mut self, name: Option<&str>, tile: Tile) -> VoxNode {
+        let instance = self
+            .models_by_content
+            .find_instance(&tile.model)
+            .unwrap_or_else(|| ModelInstance {
+                model_id: self.add_model(tile.model),
+                rotation: VoxRotation::IDENTITY,
+                offset: IVec3::ZERO,
+            });
+
+        let shape_name = name.map(|name| format!("{}_shape", name));
+        let shape = self.shape(shape_name.as_deref(), instance.model_id);
+        let frame = VoxFrame {
+            translation: self
+                .basis
+                .position_from_magica((tile.offset + instance.offset).as_vec3()),
+            rotation: self.basis.rotation_from_magica(instance.rotation),
+        };
+        self.transform(name, &[frame], shape)
     }
 
-    fn snap_quat(q: Quat) -> Quat {
-        let mat = Mat3::from_quat(q);
+    /// A transform with a shape that shows one model per frame.
+    ///
+    /// All models need the same size. Frames that didn't change share their model, models too
+    /// large for magicavoxel are split into tiles like in `model_instance`.
+    pub fn animated_models(
+        &mut self,
+        name: Option<&str>,
+        frame: VoxFrame,
+        models: &[VoxelModel],
+    ) -> VoxNode {
+        let frames = models.iter().map(VoxelModel::tiles).collect::<Vec<_>>();
+        let tile_count = frames.first().map_or(0, Vec::len);
+
+        if tile_count == 1 {
+            let model_ids = frames
+                .iter()
+                .map(|tiles| self.add_model(tiles[0].model.clone()))
+                .collect::<Vec<_>>();
+            let shape_name = name.map(|name| format!("{}_shape", name));
+            let shape = self.animated_shape(shape_name.as_deref(), &model_ids);
+            return self.transform(name, &[frame], shape);
+        }
 
-        let snapped_rows = [
-            Self::snap_vector_to_closest_axis(mat.x_axis),
-            Self::snap_vector_to_closest_axis(mat.y_axis),
-            Self::snap_vector_to_closest_axis(mat.z_axis),
-        ];
+        let mut children = vec![];
+        for tile in 0..tile_count {
+            if frames
+                .iter()
+                .all(|tiles| tiles[tile].model.voxels.is_empty())
+            {
+                continue;
+            }
 
-        let snapped_mat = Mat3::from_cols(snapped_rows[0], snapped_rows[1], snapped_rows[2]);
+            let model_ids = frames
+                .iter()
+                .map(|tiles| self.add_model(tiles[tile].model.clone()))
+                .collect::<Vec<_>>();
+            let tile_name = name.map(|name| format!("{}_tile{}", name, tile));
+            let shape_name = tile_name.as_ref().map(|name| format!("{}_shape", name));
+            let shape = self.animated_shape(shape_name.as_deref(), &model_ids);
+
+            let translation = self
+                .basis
+                .position_from_magica(frames[0][tile].offset.as_vec3());
+            children.push(self.transform(
+                tile_name.as_deref(),
+                &[VoxFrame::from_translation(translation)],
+                shape,
+            ));
+        }
 
-        Quat::from_mat3(&snapped_mat)
+        let group_name = name.map(|name| format!("{}_group", name));
+        let group = self.group(group_name.as_deref(), children);
+        self.transform(name, &[frame], group)
     }
 
-    fn snap_vector_to_closest_axis(v: Vec3) -> Vec3 {
-        let mut max_axis = Vec3::ZERO;
-        let mut max_value = -f32::INFINITY;
-
-        for axis in [
-            Vec3::X,
-            -Vec3::X, // +X, -X
-            Vec3::Y,
-            -Vec3::Y, // +Y, -Y
-            Vec3::Z,
-            -Vec3::Z, // +Z, -Z
-        ] {
-            let dot = v.dot(axis);
-            if dot > max_value {
-                max_value = dot;
-                max_axis = axis;
-            }
+    /// A model from voxel coordinates and a `size` in source axes.
+    pub fn voxel_model(
+        &self,
+        size: UVec3,
+        voxels: impl IntoIterator<Item = (UVec3, u8)>,
+    ) -> VoxelModel {
+        VoxelModel {
+            size: self.basis.size(size),
+            voxels: voxels
+                .into_iter()
+                .map(|(position, color_index)| (self.basis.voxel(position, size), color_index))
+                .collect(),
         }
+    }
 
-        max_axis
+    /// Offset from the minimum corner of a model with the given source `size` to the point
+    /// magicavoxel puts on the translation of its transform.
+    pub fn model_pivot(&self, size: UVec3) -> IVec3 {
+        self.basis.pivot(size)
+    }
+
+    pub fn basis(&self) -> Basis {
+        self.basis
+    }
+
+    fn frame_to_magica(&self, frame: VoxFrame) -> TransformFrame {
+        TransformFrame {
+            translation: self.basis.position(frame.translation).round().as_ivec3(),
+            rotation: self.basis.rotation_to_magica(frame.rotation),
+            ..Default::default()
+        }
     }
+}
 
-    fn quat_to_u8(q: Quat) -> u8 {
-        // Convert quaternion to snapped rotation matrix
-        let mat = Mat3::from_quat(q);
+/// A single keyframe of a transform node, with its rotation already snapped to what `_r` can
+/// store.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct VoxFrame {
+    pub translation: Vec3,
+    pub rotation: VoxRotation,
+}
 
-        // Find the indices and signs of the non-zero entries in each row
-        let mut indices = [0u8; 3];
-        let mut signs = [0u8; 3];
+impl VoxFrame {
+    pub fn from_translation(translation: Vec3) -> Self {
+        Self {
+            translation,
+            rotation: VoxRotation::IDENTITY,
+        }
+    }
+}
 
-        for (i, row) in [mat.x_axis, mat.z_axis, mat.y_axis].iter().enumerate() {
-            let (index, sign) = Self::find_non_zero_index_and_sign(*row);
-            indices[i] = index;
-            signs[i] = sign;
+impl From<Transform> for VoxFrame {
+    fn from(transform: Transform) -> Self {
+        Self {
+            translation: transform.translation,
+            rotation: VoxRotation::from_quat(transform.rotation),
         }
+    }
+}
 
-        // Encode into a single u8
-        (indices[0] << 0) | // First row index (bits 0-1)
-        (indices[1] << 2) | // Second row index (bits 2-3)
-        (signs[0] << 4) |   // First row sign (bit 4)
-        (signs[1] << 5) |   // Second row sign (bit 5)
-        (signs[2] << 6) // Third row sign (bit 6)
+/// The skin called `skin` or with that index, or the first one.
+fn select_skin<'a>(document: &'a Document, skin: Option<&str>) -> Result<gltf::Skin<'a>> {
+    match skin {
+        Some(skin) => document
+            .skins()
+            .find(|candidate| match skin.parse::<usize>() {
+                Ok(index) => candidate.index() == index,
+                Err(_) => candidate.name() == Some(skin),
+            })
+            .ok_or_else(|| Error::MissingSkin(Some(skin.to_string()))),
+        None => document.skins().next().ok_or(Error::MissingSkin(None)),
     }
+}
+
+/// Converts the input and writes it to the output, returns the scene that was written or the
+/// first frame with `per_frame`.
+fn convert_file(options: &Options) -> Result<VoxScene> {
+    let (document, data, images) = gltf::import(&options.input)?;
+
+    let skin = select_skin(&document, options.skin.as_deref())?;
+    let mut skeleton = Skeleton::from_skin(&document, &skin);
+    options.bone_map.apply(&mut skeleton);
+    let basis = options.basis();
+
+    // the clip comes from another rig when retargeting
+    let source = match &options.animation_from {
+        Some(path) => {
+            let (document, data, _images) = gltf::import(path)?;
+            let skin = select_skin(&document, None)?;
+            let mut skeleton = Skeleton::from_skin(&document, &skin);
+            options.source_bone_map.apply(&mut skeleton);
+            Some((document, data, skeleton))
+        }
+        None => None,
+    };
+    let (clip_document, clip_data, clip_skeleton) = match &source {
+        Some((document, data, skeleton)) => (document, data, skeleton),
+        None => (&document, &data, &skeleton),
+    };
+
+    let clip = animation::select_clip(clip_document, options.clip.clip.as_ref());
+    let (times, poses) = match &clip {
+        Some(animation) => {
+            let sampler = AnimationSampler::new(animation, clip_data);
+            let (clip_start, clip_end) = animation::clip_time_range(animation, clip_data);
+            let times = options.clip.sample_times(clip_start, clip_end);
+            println!(
+                "Clip: {} ({} frames at {} fps)",
+                animation.name().unwrap_or("<unnamed>"),
+                times.len(),
+                options.clip.fps
+            );
+
+            let poses = times
+                .iter()
+                .map(|time| clip_skeleton.globals(&clip_skeleton.sample_locals(&sampler, *time)))
+                .collect();
+            (times, poses)
+        }
+        None => match &options.clip.clip {
+            Some(clip) => return Err(Error::MissingClip(clip.to_string())),
+            None => (
+                vec![0.0],
+                vec![clip_skeleton.globals(&clip_skeleton.rest_locals())],
+            ),
+        },
+    };
+    let mut poses = match &source {
+        Some(_) => retarget::retarget(clip_skeleton, &poses, &skeleton, basis.source_up())?,
+        None => poses,
+    };
+
+    let root_offsets = match options.root_motion {
+        RootMotion::Keep => None,
+        RootMotion::InPlace | RootMotion::Extract => {
+            let root = root_motion::root_bone(&skeleton, options.root_bone.as_deref())
+                .ok_or_else(|| Error::MissingBone(options.root_bone.clone().unwrap_or_default()))?;
+            let offsets = root_motion::extract_root_motion(&mut poses, root, basis.source_up());
+            (options.root_motion == RootMotion::Extract).then_some(offsets)
+        }
+    };
+
+    // the root is named after the glTF scene, or the input file
+    let root_name = document
+        .default_scene()
+        .and_then(|scene| scene.name().map(str::to_string))
+        .or_else(|| {
+            Path::new(&options.input)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+        })
+        .unwrap_or_else(|| "root".to_string());
 
-    /// Find the index and sign of the non-zero entry in a given row of the matrix.
-    fn find_non_zero_index_and_sign(row: Vec3) -> (u8, u8) {
-        let mut index = 0;
-        let mut max_value = row[0].abs();
-        let mut sign = if row[0] >= 0.0 { 0 } else { 1 };
+    let build_scene = |poses: &[Vec<Transform>], root_offsets: Option<&[Vec3]>| {
+        let mut vox_data = VoxScene::new(basis);
+        vox_data.set_root_name(&root_name);
+        for color in &options.palette {
+            vox_data.color_index(*color);
+        }
+        let mut roots = if options.bake {
+            let mesh = SkinnedMesh::from_skin(&document, &data, &skin)?;
+            if mesh.triangles.is_empty() {
+                return Err(Error::Unsupported(format!(
+                    "no skinned triangle mesh uses skin {:?}",
+                    skin.name()
+                )));
+            }
+
+            let frames = poses
+                .iter()
+                .map(|globals| {
+                    let joint_matrices =
+                        skinning::joint_matrices(&skin, &data, &skeleton, globals, mesh.global);
+                    mesh.pose(&joint_matrices)
+                        .into_iter()
+                        .map(|position| position * options.rig.scale)
+                        .collect()
+                })
+                .collect::<Vec<_>>();
+
+            let name = skin.name().unwrap_or("mesh");
+            vec![bake::write_baked(
+                &mut vox_data,
+                name,
+                &mesh,
+                &frames,
+                &images,
+            )]
+        } else {
+            rig::write_rig(&mut vox_data, &skeleton, poses, &options.rig)
+        };
+
+        if let Some(offsets) = root_offsets {
+            let track = offsets
+                .iter()
+                .map(|offset| VoxFrame::from_translation(*offset * options.rig.scale))
+                .collect::<Vec<_>>();
+            let group = vox_data.group(Some("root_motion_group"), roots);
+            roots = vec![vox_data.transform(Some("root_motion"), &track, group)];
+        }
+
+        for root in roots {
+            vox_data.add_to_root(root);
+        }
 
-        for i in 1..3 {
-            if row[i].abs() > max_value {
-                index = i as u8;
-                max_value = row[i].abs();
-                sign = if row[i] >= 0.0 { 0 } else { 1 };
+        let mut scene = match &options.merge_into {
+            Some(path) => {
+                let mut scene = VoxScene::load(path)?;
+                scene.merge(vox_data)?;
+                scene
+            }
+            None => vox_data,
+        };
+
+        if !options.hide.is_empty() {
+            for name in &options.hide {
+                match scene.root_mut().find_mut(name) {
+                    Some(node) => node.set_attribute("_hidden", "1"),
+                    None => error::warn(format!("no node called {:?} to hide", name)),
+                }
             }
         }
+        Ok(scene)
+    };
 
-        (index, sign)
+    // the meshes and the .qb go next to the .vox
+    let save = |scene: &VoxScene, path: &Path| -> Result<()> {
+        scene.save(path)?;
+        if options.qubicle {
+            qubicle::write_qb(scene, &path.with_extension("qb"))?;
+        }
+        let Some(format) = options.mesh_format else {
+            return Ok(());
+        };
+        // the skinned mesh is the same for every frame, it is written once below
+        if options.skinned_mesh {
+            return Ok(());
+        }
+        let mesh_path = path.with_extension(format.extension());
+        match format {
+            MeshFormat::Obj => obj::write_obj(scene, &options.mesh_settings(), &mesh_path),
+            MeshFormat::Gltf | MeshFormat::Glb => vox_gltf::write_gltf(
+                scene,
+                &options.mesh_settings(),
+                options.clip.fps,
+                &mesh_path,
+            ),
+        }
+    };
+
+    let scene = if options.per_frame {
+        let clip_name = clip.as_ref().and_then(|animation| animation.name());
+        let (directory, prefix) = frames::frame_prefix(Path::new(&options.output), clip_name);
+        let mut manifest = frames::FrameManifest::new(
+            clip_name,
+            options.clip.fps,
+            options.clip.loop_mode,
+            &prefix,
+            &times,
+        );
+
+        // every file holds the rig in place, the root motion only goes into the manifest
+        if let Some(offsets) = &root_offsets {
+            for (frame, offset) in manifest.frames.iter_mut().zip(offsets) {
+                frame.root_offset = Some(offset.to_array());
+            }
+        }
+
+        let mut first = None;
+        for (pose, frame) in poses.iter().zip(&manifest.frames) {
+            let scene = build_scene(std::slice::from_ref(pose), None)?;
+            save(&scene, &directory.join(&frame.file))?;
+            first.get_or_insert(scene);
+        }
+        manifest.save(directory.join(format!("{}.json", prefix)))?;
+        first.unwrap_or_else(|| VoxScene::new(basis))
+    } else {
+        let scene = build_scene(&poses, root_offsets.as_deref())?;
+        save(&scene, Path::new(&options.output))?;
+        scene
+    };
+
+    // the voxels are skinned in rest pose, the animations come from the input as they are
+    if let (Some(format), true) = (options.mesh_format, options.skinned_mesh) {
+        let rest = skeleton.globals(&skeleton.rest_locals());
+        let rest_scene = build_scene(&[rest], None)?;
+        skinned_gltf::write_skinned_gltf(
+            &rest_scene,
+            &options.mesh_settings(),
+            &document,
+            &data,
+            &skin,
+            &skeleton,
+            &Path::new(&options.output).with_extension(format.extension()),
+        )?;
     }
+    Ok(scene)
 }
 
-struct GltfScene((Document, Vec<buffer::Data>, Vec<image::Data>));
-
 fn convert(
+    options: Res<Options>,
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
 ) {
-    let (document, data, _images) = gltf::import("./input/character.glb").unwrap();
-
-    let skin = document.skins().next().unwrap();
-    let joint_indices = skin.joints().map(|joint| joint.index()).collect::<Vec<_>>();
-
-    println!("Joint indices: {:?}", joint_indices);
-
-    fn do_skin(
-        node: gltf::Node,
-        skin: &gltf::Skin,
-        depth: usize,
-        parent_transform: Transform,
-        (commands, meshes, materials): (
-            &mut Commands,
-            &mut ResMut<Assets<Mesh>>,
-            &mut ResMut<Assets<StandardMaterial>>,
-        ),
-    ) -> Entity {
-        let joint = skin.joints().find(|joint| joint.index() == node.index());
-        if joint.is_none() {
-            panic!("Joint not found");
-        }
-        let transform = transform_from_gltf(node.transform());
-        // let transform = parent_transform * transform;
-        let depth_as_string = std::iter::repeat("  ").take(depth).collect::<String>();
-        println!(
-            "{}{}Joint: {:?} - {:?}",
-            depth_as_string,
-            node.index(),
-            node.name().unwrap(),
-            transform.translation,
-        );
+    let scene = convert_file(&options).unwrap_or_else(|error| exit_with_error(&error));
+    preview::spawn_scene(
+        &scene,
+        &options.mesh_settings(),
+        &mut commands,
+        &mut meshes,
+        &mut materials,
+    );
+}
 
-        let parent = commands
-            .spawn((
-                transform,
-                Mesh3d(meshes.add(Mesh::from(Sphere::new(0.1)))),
-                MeshMaterial3d(materials.add(Color::srgb_u8(
-                    rand::random(),
-                    rand::random(),
-                    rand::random(),
-                ))),
-                Name::new(node.name().unwrap().to_string()),
-            ))
-            .id();
+/// Prints every transform of a `.vox` file with its decoded rotations.
+fn inspect_vox(path: &str) -> Result<()> {
+    let data =
+        dot_vox::load(path).map_err(|error| Error::InvalidVox(format!("{}: {}", path, error)))?;
+    let basis = Basis::default();
 
-        let mut children = vec![];
-        for child in node.children() {
-            let ent = do_skin(
-                child,
-                skin,
-                depth + 1,
-                transform,
-                (commands, meshes, materials),
-            );
-            children.push(ent);
-        }
-
-        //  we just create a bone with length of 1 in the direction of the current joint
-        if children.is_empty() {
-            let bone_length = 1.0;
-            // let bone_transform =
-            //     Transform::from_translation(Vec3::new(0.0, bone_length / 2.0, 0.0))
-            //         * Transform::from_rotation(Quat::from_rotation_x(std::f32::consts::PI / 2.0))
-            //         * Transform::from_scale(Vec3::new(0.1, bone_length, 0.1));
-            let extra = commands.spawn((
-                Transform::from_translation(Vec3::new(0.0, bone_length, 0.0)),
-                Mesh3d(meshes.add(Mesh::from(Sphere::new(0.1)))),
-                MeshMaterial3d(materials.add(Color::srgb_u8(
-                    rand::random(),
-                    rand::random(),
-                    rand::random(),
-                ))),
-            ));
+    for (i, node) in data.scenes.iter().enumerate() {
+        let dot_vox::SceneNode::Transform {
+            attributes,
+            frames,
+            child,
+            ..
+        } = node
+        else {
+            continue;
+        };
 
-            children.push(extra.id());
+        println!(
+            "{} Transform {:?} -> {}",
+            i,
+            attributes.get("_name").map(String::as_str).unwrap_or(""),
+            child
+        );
+        for frame in frames {
+            let translation = frame.attributes.get("_t").map(String::as_str);
+            let Some(rotation) = frame.attributes.get("_r") else {
+                println!("    _t {:?}", translation.unwrap_or("0 0 0"));
+                continue;
+            };
+
+            let decoded = rotation
+                .parse::<u8>()
+                .map_err(|_| format!("{:?} is not a byte", rotation))
+                .and_then(|byte| {
+                    let mat = VoxRotation::from_byte(byte)
+                        .map_err(|e| e.to_string())?
+                        .to_mat3();
+                    Ok((mat, basis.quat_from_magica(byte)))
+                });
+            match decoded {
+                Ok((mat, quat)) => println!(
+                    "    _t {:?} _r {} rows {:?} {:?} {:?} -> {}",
+                    translation.unwrap_or("0 0 0"),
+                    rotation,
+                    mat.row(0).to_array(),
+                    mat.row(1).to_array(),
+                    mat.row(2).to_array(),
+                    match quat {
+                        Ok(quat) => format!("{:?}", quat),
+                        Err(error) => error.to_string(),
+                    }
+                ),
+                Err(error) => println!(
+                    "    _t {:?} _r {} invalid: {}",
+                    translation.unwrap_or("0 0 0"),
+                    rotation,
+                    error
+                ),
+            }
         }
-
-        commands.entity(parent).add_children(&children);
-
-        parent
     }
 
-    // do_skin(
-    //     skin.joints().next().unwrap(),
-    //     &skin,
-    //     0,
-    //     Transform::default(),
-    //     (&mut commands, &mut meshes, &mut materials),
-    // );
-
-    // let vox_data = dot_vox::load("input/cube_rotate.vox").unwrap();
-
-    let mut vox_data = VoxScene::new();
-
-    let idx = vox_data.add_from_aabb(
-        "Henk".to_string(),
-        Transform::default(),
-        Vec3::new(1.0, 1.0, 1.0),
-    );
-    let idx_2 = vox_data.add_from_aabb(
-        "Henk2".to_string(),
-        Transform::from_translation(Vec3::new(1.0, 1.0, 1.0)),
-        Vec3::new(1.0, 2.0, 1.0),
-    );
-
-    let group_idx = vox_data.add_group(
-        Transform::from_translation(Vec3::new(0.0, 0.0, 0.0))
-            .with_rotation(Quat::from_rotation_x(90.0_f32.to_radians())),
-        vec![idx, idx_2],
-    );
+    Ok(())
+}
 
-    vox_data.add_to_root(group_idx);
-    vox_data.save("output.vox");
-
-    commands
-        .spawn((
-            Mesh3d(meshes.add(Mesh::from(Cuboid::from_size(Vec3::new(1.0, 1.0, 1.0))))),
-            Transform::from_translation(Vec3::new(0.0, 0.0, 0.0)),
-            MeshMaterial3d(materials.add(Color::srgb_u8(
-                rand::random(),
-                rand::random(),
-                rand::random(),
-            ))),
-        ))
-        .with_child((
-            Mesh3d(meshes.add(Mesh::from(Cuboid::from_size(Vec3::new(1.0, 2.0, 1.0))))),
-            Transform::from_translation(Vec3::new(1.0, 1.0, -1.0)),
-            MeshMaterial3d(materials.add(Color::srgb_u8(
-                rand::random(),
-                rand::random(),
-                rand::random(),
-            ))),
-        ));
+/// Writes `input` as a glTF to the output when that ends in `.gltf` or `.glb`, otherwise next to
+/// the input.
+fn vox_to_gltf(options: &Options, input: &str) -> Result<()> {
+    let options = options.for_input(input)?;
+    let output = Path::new(&options.output);
+    let output = match output.extension().and_then(|extension| extension.to_str()) {
+        Some("gltf" | "glb") => output.to_path_buf(),
+        _ => Path::new(input).with_extension("gltf"),
+    };
 
-    // println!("vox_data: {:?}", vox_data.scenes);
-
-    // let joint_ids
-    // skin.joints().for_each(|joint| {
-
-    //     println!("Joint: {:?} {:?} {:?}", joint.name(), transform_from_gltf(joint.transform()), joint.children());
-    // });
-
-    // let default_scene = document.default_scene().unwrap();
-
-    // fn create_stuff(
-    //     node: gltf::Node,
-    //     commands: &mut Commands,
-    //     meshes: &mut ResMut<Assets<Mesh>>,
-    //     materials: &mut ResMut<Assets<StandardMaterial>>,
-    //     parent: Option<Entity>
-    // ) -> Entity {
-    //     let entity = commands.spawn((
-    //         transform_from_gltf(node.transform()),
-    //         Mesh3d(meshes.add(Mesh::from(Cuboid::from_size(Vec3::splat(1.0))))),
-    //         MeshMaterial3d(materials.add(Color::srgb_u8(
-    //             rand::random(),
-    //             rand::random(),
-    //             rand::random(),
-    //         ))),
-    //     )).id();
-    //     if let Some(parent) = parent {
-    //         commands.entity(parent).add_child(entity);
-    //     }
-    //     println!("Node: {:?}", node.name());
-
-    //     for child in node.children() {
-    //         let child_entity = create_stuff(child, commands, meshes, materials, parent);
-    //         commands.entity(entity).add_child(child_entity);
-    //     }
-
-    //     entity
-    // }
-
-    // create_stuff(default_scene.nodes().next().unwrap(), &mut commands, &mut meshes, &mut materials, None);
-
-    // for animation in document.animations() {
-    //     for channel in animation.channels() {
-    //         let reader = channel.reader(|buffer| Some(&data[buffer.index()]));
-
-    //         let target = channel.target();
-    //         println!("Target: {:?}", target.node().name());
-
-    //     }
-    // }
-
-    // for node in default_scene.nodes() {
-    //     let parent = commands.spawn((
-    //         transform_from_gltf(node.transform()),
-    //         Mesh3d(meshes.add(Mesh::from(Cuboid::from_size(Vec3::splat(1.0))))),
-    //     ));
-    //     println!("Node: {:?}", node.name());
-    // }
-
-    // for node in document
-    // .nodes() {
-    //     println!("Node: {:?}", node.name());
-    // }
-
-    // let armature_name = "b_Hip_01";
-    // let root_armature = document
-    //     .nodes()
-    //     .find(|node| node.name().unwrap() == armature_name)
-    //     .unwrap();
-
-    // let mut voxel_aabbs: Slab<VoxelObject> = Slab::new();
-
-    // create_voxel_aabbs_from_skeleton(root_armature, 0, &mut voxel_aabbs, None);
-
-    // for (_, voxel) in voxel_aabbs.iter() {
-    //     println!("Voxel: {:?}", voxel);
-    //     // shape::Cube
-    //     commands.spawn((
-    //         Mesh3d(meshes.add(Mesh::from(Cuboid::from_size(
-    //             (voxel.aabb.half_size() * 2.0).into(),
-    //         )))),
-    //         Transform::from_translation(voxel.aabb.center().into()),
-    //         MeshMaterial3d(materials.add(Color::srgb_u8(
-    //             rand::random(),
-    //             rand::random(),
-    //             rand::random(),
-    //         ))),
-    //     ));
-    // }
-
-    // let mut dot_vox_data = DotVoxData {
-    //     version: 150,
-    //     layers: vec![dot_vox::Layer {
-    //         attributes: Default::default(),
-    //     }],
-    //     models: vec![],
-    //     palette: vec![
-    //         dot_vox::Color {
-    //             r: 0,
-    //             g: 0,
-    //             b: 0,
-    //             a: 255,
-    //         };
-    //         256
-    //     ],
-    //     materials: vec![],
-    //     scenes: vec![
-    //         dot_vox::SceneNode::Transform {
-    //             attributes: Default::default(),
-    //             child: 1,
-    //             layer_id: 4294967295,
-    //             frames: vec![dot_vox::Frame {
-    //                 attributes: Default::default(),
-    //             }],
-    //         },
-    //         dot_vox::SceneNode::Group {
-    //             attributes: Default::default(),
-    //             children: vec![],
-    //         },
-    //     ],
-    // };
-
-    // for material_idx in 0..256 {
-    //     let mut material = dot_vox::Material {
-    //         id: material_idx as u32,
-    //         properties: Default::default(),
-    //     };
-
-    //     let properties = [
-    //         ("_rough", "0.1"),
-    //         ("_ior", "0.3"),
-    //         ("_spec", "0.5"),
-    //         ("_weight", "1"),
-    //         ("_type", "_diffuse"),
-    //     ];
-
-    //     for (k, v) in properties.iter() {
-    //         material.properties.insert(k.to_string(), v.to_string());
-    //     }
-
-    //     dot_vox_data.materials.push(material);
-    // }
-
-    // let layer_idx = 0;
-    // let mut transform_nodes_indices = vec![];
-
-    // for (_, object) in voxel_aabbs.iter() {
-    //     let u_size = (object.aabb.half_size() * 2.0).as_uvec3();
-    //     let model: dot_vox::Model = dot_vox::Model {
-    //         // magicavoxel uses xzy
-    //         size: dot_vox::Size {
-    //             x: u_size.x as u32,
-    //             y: u_size.z as u32,
-    //             z: u_size.y as u32,
-    //         },
-    //         voxels: vec![],
-    //     };
-    //     dot_vox_data.models.push(model);
-
-    //     let mut frame = dot_vox::Frame {
-    //         attributes: Default::default(),
-    //     };
-    //     frame.attributes.insert(
-    //         "_t".to_string(),
-    //         format!(
-    //             "{} {} {}",
-    //             object.aabb.center().x as i32,
-    //             object.aabb.center().z as i32,
-    //             object.aabb.center().y as i32
-    //         ),
-    //     );
-
-    //     let mut transform_dict = dot_vox::Dict::new();
-    //     transform_dict.insert("_name".to_string(), object.name.clone());
-    //     dot_vox_data.scenes.push(dot_vox::SceneNode::Transform {
-    //         attributes: transform_dict,
-    //         frames: vec![frame],
-    //         // shape id
-    //         child: dot_vox_data.scenes.len() as u32 + 1,
-    //         layer_id: layer_idx,
-    //     });
-
-    //     transform_nodes_indices.push(dot_vox_data.scenes.len() as u32 - 1);
-
-    //     dot_vox_data.scenes.push(dot_vox::SceneNode::Shape {
-    //         attributes: Default::default(),
-    //         models: vec![dot_vox::ShapeModel {
-    //             model_id: (dot_vox_data.models.len() - 1) as u32,
-    //             attributes: Default::default(),
-    //         }],
-    //     });
-    // }
-    // dot_vox_data.scenes[1] = dot_vox::SceneNode::Group {
-    //     attributes: Default::default(),
-    //     children: transform_nodes_indices,
-    // };
-
-    // println!("dot_vox_data: {:?}", dot_vox_data.scenes);
-    // let mut vox_file = BufWriter::new(File::create("output.vox").unwrap());
-    // dot_vox_data.write_vox(&mut vox_file).unwrap();
-
-    // commands.spawn((
-    //     DirectionalLight {
-    //         // shadows_enabled: true,
-    //         ..Default::default()
-    //     },
-    //     Transform::from_rotation(Quat::from_rotation_x(-std::f32::consts::FRAC_PI_4)),
-    // ));
-
-    // commands.spawn((
-    //     Camera3d::default(),
-    //     Transform::from_xyz(-2.5, 10.0, 10.0).looking_at(Vec3::ZERO, Vec3::Y),
-    //     FlyCam,
-    // ));
+    let scene = VoxScene::load(input)?;
+    vox_gltf::write_gltf(&scene, &options.mesh_settings(), options.clip.fps, &output)
 }
 
 fn setup_camera(mut commands: Commands) {
@@ -646,109 +1524,6 @@ fn setup_camera(mut commands: Commands) {
     ));
 }
 
-fn create_voxel_aabbs_from_skeleton(
-    node: gltf::Node,
-    depth: usize,
-    dot_vox_data: &mut DotVoxData,
-    parent_global_transform: Transform,
-) -> u32 {
-    let local_transform = transform_from_gltf(node.transform());
-    let global_transform = parent_global_transform * local_transform;
-
-    // Current bone's end position (global position)
-    let end_position = global_transform.translation;
-
-    // If there's a parent, create a VoxelObject spanning between the parent and current node
-    let center = Vec3::new(
-        (end_position.x + parent_global_transform.translation.x) / 2.0,
-        (end_position.z + parent_global_transform.translation.z) / 2.0,
-        (end_position.y + parent_global_transform.translation.y) / 2.0,
-    );
-
-    let half_extents = Vec3::new(
-        (end_position.x - parent_global_transform.translation.x).abs() / 2.0,
-        (end_position.z - parent_global_transform.translation.z).abs() / 2.0,
-        (end_position.y - parent_global_transform.translation.y).abs() / 2.0,
-    );
-
-    // we have to make sure the AABB has atleast a size of 1
-    let half_extents = half_extents.max(Vec3::splat(BONE_VOXEL_THICKNESS / 2.0));
-
-    let u_size = (half_extents * 2.0).as_uvec3();
-    let model: dot_vox::Model = dot_vox::Model {
-        // magicavoxel uses xzy
-        size: dot_vox::Size {
-            x: u_size.x as u32,
-            y: u_size.z as u32,
-            z: u_size.y as u32,
-        },
-        voxels: vec![],
-    };
-    dot_vox_data.models.push(model);
-
-    let mut frame = dot_vox::Frame {
-        attributes: Default::default(),
-    };
-    frame.attributes.insert(
-        "_t".to_string(),
-        format!(
-            "{} {} {}",
-            center.x as i32, center.z as i32, center.y as i32
-        ),
-    );
-
-    let mut transform_dict = dot_vox::Dict::new();
-    transform_dict.insert("_name".to_string(), node.name().unwrap().to_string());
-    dot_vox_data.scenes.push(dot_vox::SceneNode::Transform {
-        attributes: transform_dict,
-        frames: vec![frame],
-        // shape id
-        child: dot_vox_data.scenes.len() as u32 + 1,
-        layer_id: 0,
-    });
-
-    let idx = dot_vox_data.scenes.len() as u32 - 1;
-
-    dot_vox_data.scenes.push(dot_vox::SceneNode::Shape {
-        attributes: Default::default(),
-        models: vec![dot_vox::ShapeModel {
-            model_id: (dot_vox_data.models.len() - 1) as u32,
-            attributes: Default::default(),
-        }],
-    });
-
-    // Some(voxel_aabbs.insert(VoxelObject {
-    //     name: node.name().unwrap().to_string(),
-    //     aabb: Aabb3d::new(center, half_extents),
-    // }))
-
-    let depth_as_string = std::iter::repeat("  ").take(depth).collect::<String>();
-    println!(
-        "{}Node: {:?} {:?}",
-        depth_as_string,
-        node.name().unwrap(),
-        global_transform
-    );
-
-    let mut children = vec![];
-    for child in node.children() {
-        children.push(create_voxel_aabbs_from_skeleton(
-            child,
-            depth + 1,
-            dot_vox_data,
-            global_transform,
-        ));
-    }
-
-    dot_vox_data.scenes.push(dot_vox::SceneNode::Group {
-        attributes: Default::default(),
-        children,
-    });
-
-    idx
-    // scene group
-}
-
 pub fn transform_from_gltf(transform: gltf::scene::Transform) -> Transform {
     let (translation, rotation, scale) = transform.decomposed();
 
@@ -0,0 +1,301 @@
+use bevy::{
+    prelude::*,
+    render::{
+        mesh::{Indices, PrimitiveTopology},
+        render_asset::RenderAssetUsages,
+    },
+};
+
+use crate::coords::Basis;
+
+/// Brightness of a vertex by how open its corner is, see `corner_occlusion`.
+const OCCLUSION: [f32; 4] = [0.5, 0.7, 0.85, 1.0];
+
+/// How voxel models are meshed and placed in the axes and units of the source.
+#[derive(Debug, Clone, Copy)]
+pub struct MeshSettings {
+    pub basis: Basis,
+    /// Voxels per unit.
+    pub scale: f32,
+    /// Darken vertices in corners and creases, through the vertex colors.
+    pub ambient_occlusion: bool,
+}
+
+/// Triangles covering the visible faces of a voxel model.
+///
+/// Positions are in voxels from the minimum corner of the model, in magicavoxel axes.
+#[derive(Debug, Clone, Default)]
+pub struct VoxelMesh {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    /// Palette index of every vertex.
+    pub colors: Vec<u8>,
+    /// Brightness of every vertex, 1 without ambient occlusion.
+    pub occlusion: Vec<f32>,
+    /// Group of the voxels every vertex belongs to, see `greedy`.
+    pub groups: Vec<u32>,
+    /// Counter clockwise seen from outside the model.
+    pub indices: Vec<u32>,
+}
+
+impl VoxelMesh {
+    /// Meshes `model` with greedy meshing: neighbouring faces of the same color that look the
+    /// same way are merged into one rectangle, as long as `group` puts their voxels in the same
+    /// group.
+    ///
+    /// With `ambient_occlusion` only faces without occluded corners merge, so the shading of a
+    /// corner stays where it belongs.
+    pub fn greedy(
+        model: &dot_vox::Model,
+        ambient_occlusion: bool,
+        group: impl Fn(IVec3) -> u32,
+    ) -> Self {
+        let size = IVec3::new(
+            model.size.x as i32,
+            model.size.y as i32,
+            model.size.z as i32,
+        );
+        let index = |position: IVec3| position.x + (position.y + position.z * size.y) * size.x;
+        let mut grid = vec![None; (size.x * size.y * size.z) as usize];
+        for voxel in &model.voxels {
+            let position = IVec3::new(voxel.x as i32, voxel.y as i32, voxel.z as i32);
+            if position.cmplt(size).all() {
+                grid[index(position) as usize] = Some(voxel.i);
+            }
+        }
+        let voxel = |position: IVec3| {
+            if position.cmpge(IVec3::ZERO).all() && position.cmplt(size).all() {
+                grid[index(position) as usize]
+            } else {
+                None
+            }
+        };
+
+        let mut mesh = Self::default();
+        for axis in 0..3 {
+            // the faces of a layer span these two axes, in this order their cross product is `axis`
+            let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+            let mut right = IVec3::ZERO;
+            right[u] = 1;
+            let mut up = IVec3::ZERO;
+            up[v] = 1;
+
+            for direction in [-1, 1] {
+                let mut normal = IVec3::ZERO;
+                normal[axis] = direction;
+
+                for layer in 0..size[axis] {
+                    // color and corner occlusion of every face in the layer that isn't hidden by
+                    // a neighbour
+                    let mut mask = vec![None; (size[u] * size[v]) as usize];
+                    for j in 0..size[v] {
+                        for i in 0..size[u] {
+                            let position = right * i + up * j + normal.abs() * layer;
+                            let in_front = position + normal;
+                            if voxel(in_front).is_some() {
+                                continue;
+                            }
+                            let Some(color) = voxel(position) else {
+                                continue;
+                            };
+
+                            let mut corners = [3; 4];
+                            if ambient_occlusion {
+                                let blocked = |offset: IVec3| voxel(in_front + offset).is_some();
+                                for (corner, (du, dv)) in
+                                    [(-1, -1), (1, -1), (1, 1), (-1, 1)].into_iter().enumerate()
+                                {
+                                    corners[corner] = corner_occlusion(
+                                        blocked(right * du),
+                                        blocked(up * dv),
+                                        blocked(right * du + up * dv),
+                                    );
+                                }
+                            }
+                            mask[(i + j * size[u]) as usize] =
+                                Some((color, group(position), corners));
+                        }
+                    }
+
+                    for j in 0..size[v] {
+                        let mut i = 0;
+                        while i < size[u] {
+                            let Some(face) = mask[(i + j * size[u]) as usize] else {
+                                i += 1;
+                                continue;
+                            };
+                            let same =
+                                |i: i32, j: i32| mask[(i + j * size[u]) as usize] == Some(face);
+
+                            let (mut width, mut height) = (1, 1);
+                            if face.2 == [3; 4] {
+                                while i + width < size[u] && same(i + width, j) {
+                                    width += 1;
+                                }
+                                while j + height < size[v]
+                                    && (i..i + width).all(|i| same(i, j + height))
+                                {
+                                    height += 1;
+                                }
+                            }
+                            for j in j..j + height {
+                                for i in i..i + width {
+                                    mask[(i + j * size[u]) as usize] = None;
+                                }
+                            }
+
+                            let corner =
+                                right * i + up * j + normal.abs() * (layer + (direction + 1) / 2);
+                            let corners = [
+                                corner,
+                                corner + right * width,
+                                corner + right * width + up * height,
+                                corner + up * height,
+                            ];
+                            mesh.push_quad(corners.map(|corner| corner.as_vec3()), normal, face);
+
+                            i += width;
+                        }
+                    }
+                }
+            }
+        }
+        mesh
+    }
+
+    /// The mesh of a model placed the way magicavoxel places it on a transform, in the axes and
+    /// units of the source. `None` for a model without voxels.
+    pub fn from_model(model: &dot_vox::Model, settings: &MeshSettings) -> Option<Self> {
+        Self::from_model_grouped(model, settings, |_| 0)
+    }
+
+    /// Like `from_model`, with the faces grouped as in `greedy`.
+    pub fn from_model_grouped(
+        model: &dot_vox::Model,
+        settings: &MeshSettings,
+        group: impl Fn(IVec3) -> u32,
+    ) -> Option<Self> {
+        if model.voxels.is_empty() {
+            return None;
+        }
+        let mut mesh = Self::greedy(model, settings.ambient_occlusion, group);
+
+        for position in &mut mesh.positions {
+            *position = model_point(model, *position, settings);
+        }
+        for normal in &mut mesh.normals {
+            *normal = settings.basis.position_from_magica(*normal);
+        }
+        // mirrored axes turn the triangles inside out
+        if settings.basis.is_reflection() {
+            for triangle in mesh.indices.chunks_mut(3) {
+                triangle.swap(1, 2);
+            }
+        }
+        Some(mesh)
+    }
+
+    /// Linear color of every vertex, darkened by its occlusion.
+    pub fn vertex_colors(&self, palette: &[dot_vox::Color]) -> Vec<Vec4> {
+        self.colors
+            .iter()
+            .zip(&self.occlusion)
+            .map(|(index, occlusion)| {
+                let color = palette[*index as usize];
+                let color = Color::srgba_u8(color.r, color.g, color.b, color.a).to_linear();
+                let rgb = Vec3::new(color.red, color.green, color.blue) * *occlusion;
+                rgb.extend(color.alpha)
+            })
+            .collect()
+    }
+
+    pub fn to_bevy_mesh(&self, palette: &[dot_vox::Color]) -> Mesh {
+        let arrays = |vectors: &[Vec3]| vectors.iter().map(|v| v.to_array()).collect::<Vec<_>>();
+        let colors = self
+            .vertex_colors(palette)
+            .into_iter()
+            .map(|color| color.to_array())
+            .collect::<Vec<_>>();
+
+        Mesh::new(
+            PrimitiveTopology::TriangleList,
+            RenderAssetUsages::default(),
+        )
+        .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, arrays(&self.positions))
+        .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, arrays(&self.normals))
+        .with_inserted_attribute(Mesh::ATTRIBUTE_COLOR, colors)
+        .with_inserted_indices(Indices::U32(self.indices.clone()))
+    }
+
+    /// Adds a rectangle, `corners` go counter clockwise around `normal` when it is positive.
+    fn push_quad(
+        &mut self,
+        corners: [Vec3; 4],
+        normal: IVec3,
+        (color, group, occlusion): (u8, u32, [u8; 4]),
+    ) {
+        let first = self.positions.len() as u32;
+        self.positions.extend(corners);
+        self.normals.extend([normal.as_vec3(); 4]);
+        self.colors.extend([color; 4]);
+        self.groups.extend([group; 4]);
+        self.occlusion
+            .extend(occlusion.map(|corner| OCCLUSION[corner as usize]));
+
+        // split along the diagonal between the brighter corners, otherwise the shading of the
+        // two triangles doesn't match up
+        let flip = occlusion[0] + occlusion[2] < occlusion[1] + occlusion[3];
+        let order = match (normal.cmpgt(IVec3::ZERO).any(), flip) {
+            (true, false) => [0, 1, 2, 0, 2, 3],
+            (true, true) => [1, 2, 3, 1, 3, 0],
+            (false, false) => [0, 2, 1, 0, 3, 2],
+            (false, true) => [1, 3, 2, 1, 0, 3],
+        };
+        self.indices.extend(order.map(|index| first + index));
+    }
+}
+
+/// A point in voxels from the minimum corner of a model, relative to its transform in the axes
+/// and units of the source.
+pub fn model_point(model: &dot_vox::Model, point: Vec3, settings: &MeshSettings) -> Vec3 {
+    // magicavoxel puts the voxel at half the size of a model on the translation
+    let pivot = (UVec3::new(model.size.x, model.size.y, model.size.z) / 2).as_vec3();
+    settings.basis.position_from_magica(point - pivot) / settings.scale
+}
+
+/// How open the corner of a face is, from 0 when both sides next to it are blocked to 3 when
+/// nothing is.
+fn corner_occlusion(side: bool, other_side: bool, corner: bool) -> u8 {
+    if side && other_side {
+        0
+    } else {
+        3 - side as u8 - other_side as u8 - corner as u8
+    }
+}
+
+/// File format of the mesh export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MeshFormat {
+    Obj,
+    Gltf,
+    Glb,
+}
+
+impl MeshFormat {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "obj" => Some(MeshFormat::Obj),
+            "gltf" => Some(MeshFormat::Gltf),
+            "glb" => Some(MeshFormat::Glb),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            MeshFormat::Obj => "obj",
+            MeshFormat::Gltf => "gltf",
+            MeshFormat::Glb => "glb",
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use std::{collections::HashMap, fmt::Write, fs, path::Path};
+
+use bevy::prelude::*;
+
+use crate::{
+    error::{Error, Result},
+    mesh::{MeshSettings, VoxelMesh},
+    vox_gltf::rest_models,
+    VoxScene,
+};
+
+/// Writes the models of a scene in rest pose as a Wavefront OBJ, one object per shape.
+///
+/// The color of every vertex follows its position, which is not in the spec but is read by
+/// most tools that read OBJ.
+pub fn write_obj(scene: &VoxScene, settings: &MeshSettings, path: &Path) -> Result<()> {
+    let mut meshes = HashMap::new();
+    let mut obj = String::from("# skeleton-to-vox\n");
+    // indices start at 1 and count the vertices of every object before
+    let mut first = 1;
+
+    for (i, (name, model_id, global)) in rest_models(scene.root(), Mat4::IDENTITY, settings)
+        .into_iter()
+        .enumerate()
+    {
+        let model = scene
+            .models()
+            .get(model_id as usize)
+            .ok_or_else(|| Error::InvalidVox(format!("model {} doesn't exist", model_id)))?;
+        let Some(mesh) = meshes
+            .entry(model_id)
+            .or_insert_with(|| VoxelMesh::from_model(model, settings))
+        else {
+            continue;
+        };
+
+        match name {
+            Some(name) => writeln!(obj, "o {}", name),
+            None => writeln!(obj, "o model{}", i),
+        }
+        .unwrap();
+        for (position, color) in mesh
+            .positions
+            .iter()
+            .zip(mesh.vertex_colors(scene.palette()))
+        {
+            let position = global.transform_point3(*position);
+            let color = Srgba::from(LinearRgba::from_f32_array(color.to_array()));
+            writeln!(
+                obj,
+                "v {} {} {} {} {} {}",
+                position.x, position.y, position.z, color.red, color.green, color.blue
+            )
+            .unwrap();
+        }
+        for normal in &mesh.normals {
+            let normal = global.transform_vector3(*normal).normalize();
+            writeln!(obj, "vn {} {} {}", normal.x, normal.y, normal.z).unwrap();
+        }
+        // a mirroring transform turns the triangles inside out
+        let mirrored = global.determinant() < 0.0;
+        for triangle in mesh.indices.chunks(3) {
+            let [a, b, c] = [0, 1, 2].map(|corner| triangle[corner] + first);
+            let (b, c) = if mirrored { (c, b) } else { (b, c) };
+            writeln!(obj, "f {a}//{a} {b}//{b} {c}//{c}").unwrap();
+        }
+        first += mesh.positions.len() as u32;
+    }
+
+    fs::write(path, obj).map_err(|error| Error::Io(path.to_path_buf(), error))
+}
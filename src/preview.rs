@@ -0,0 +1,46 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::{
+    mesh::{MeshSettings, VoxelMesh},
+    vox_gltf::rest_models,
+    VoxScene,
+};
+
+/// Spawns every model of `scene` in rest pose, meshed the same way as the mesh export.
+pub fn spawn_scene(
+    scene: &VoxScene,
+    settings: &MeshSettings,
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+) {
+    // the colors are in the vertices, so one white material does for every model
+    let material = materials.add(StandardMaterial {
+        base_color: Color::WHITE,
+        perceptual_roughness: 1.0,
+        ..Default::default()
+    });
+
+    let mut handles = HashMap::new();
+    for (name, model_id, global) in rest_models(scene.root(), Mat4::IDENTITY, settings) {
+        let Some(model) = scene.models().get(model_id as usize) else {
+            continue;
+        };
+        let handle = handles.entry(model_id).or_insert_with(|| {
+            VoxelMesh::from_model(model, settings)
+                .map(|mesh| meshes.add(mesh.to_bevy_mesh(scene.palette())))
+        });
+        let Some(handle) = handle.clone() else {
+            continue;
+        };
+
+        commands.spawn((
+            Mesh3d(handle),
+            MeshMaterial3d(material.clone()),
+            Transform::from_matrix(global),
+            Name::new(name.unwrap_or("model").to_string()),
+        ));
+    }
+}
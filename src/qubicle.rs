@@ -0,0 +1,172 @@
+use std::{fs, path::Path};
+
+use bevy::prelude::*;
+
+use crate::{
+    error::{Error, Result},
+    rotation::VoxRotation,
+    vox_tree::VoxNode,
+    VoxScene,
+};
+
+const VERSION: [u8; 4] = [1, 1, 0, 0];
+const COLOR_FORMAT_RGBA: u32 = 0;
+const LEFT_HANDED: u32 = 0;
+const UNCOMPRESSED: u32 = 0;
+// alpha says whether a voxel is there, instead of which of its sides are visible
+const NO_VISIBILITY_MASK: u32 = 0;
+
+/// A box of voxels placed in the qubicle scene.
+struct Matrix {
+    name: String,
+    position: IVec3,
+    size: UVec3,
+    /// Indexed by x, then y, then z.
+    colors: Vec<[u8; 4]>,
+}
+
+/// Writes the models of a scene in rest pose as Qubicle Binary, one matrix per shape named after
+/// the transform above it.
+///
+/// Qubicle has no rotations, so they are baked into the voxels. Its y axis points up, the
+/// magicavoxel axes are written as (x, z, y), which makes the file left-handed.
+pub fn write_qb(scene: &VoxScene, path: &Path) -> Result<()> {
+    let mut matrices = vec![];
+    collect_matrices(
+        scene,
+        scene.root(),
+        (VoxRotation::IDENTITY, IVec3::ZERO),
+        None,
+        &mut matrices,
+    )?;
+
+    let mut qb = vec![];
+    qb.extend(VERSION);
+    for value in [
+        COLOR_FORMAT_RGBA,
+        LEFT_HANDED,
+        UNCOMPRESSED,
+        NO_VISIBILITY_MASK,
+        matrices.len() as u32,
+    ] {
+        qb.extend(value.to_le_bytes());
+    }
+    for matrix in matrices {
+        // names are prefixed with a single byte for their length
+        let mut name = matrix.name.as_bytes();
+        if name.len() > u8::MAX as usize {
+            name = &name[..u8::MAX as usize];
+        }
+        qb.push(name.len() as u8);
+        qb.extend(name);
+        for value in matrix.size.to_array() {
+            qb.extend(value.to_le_bytes());
+        }
+        for value in matrix.position.to_array() {
+            qb.extend(value.to_le_bytes());
+        }
+        qb.extend(matrix.colors.into_iter().flatten());
+    }
+
+    fs::write(path, qb).map_err(|error| Error::Io(path.to_path_buf(), error))
+}
+
+/// Adds a matrix for every shape below `node`, placed by the first keyframe of the transforms
+/// above it. `parent` is the rotation and translation of those in magicavoxel axes.
+fn collect_matrices(
+    scene: &VoxScene,
+    node: &VoxNode,
+    parent: (VoxRotation, IVec3),
+    name: Option<&str>,
+    matrices: &mut Vec<Matrix>,
+) -> Result<()> {
+    match node {
+        VoxNode::Transform(transform) => {
+            let frame = transform
+                .frames
+                .iter()
+                .min_by_key(|frame| frame.keyframe.unwrap_or(0))
+                .cloned()
+                .unwrap_or_default();
+            let (rotation, translation) = parent;
+            let global = (
+                rotation * frame.rotation,
+                translation + rotation.transform_ivec3(frame.translation),
+            );
+            collect_matrices(scene, &transform.child, global, node.name(), matrices)
+        }
+        VoxNode::Group(group) => group
+            .children
+            .iter()
+            .try_for_each(|child| collect_matrices(scene, child, parent, name, matrices)),
+        VoxNode::Shape(shape) => {
+            // a shape with a model per keyframe starts out with the first one
+            let Some(model) = shape.models.iter().min_by_key(|model| {
+                model
+                    .attributes
+                    .get("_f")
+                    .and_then(|keyframe| keyframe.parse::<u32>().ok())
+                    .unwrap_or(0)
+            }) else {
+                return Ok(());
+            };
+            let model = scene.models().get(model.model_id as usize).ok_or_else(|| {
+                Error::InvalidVox(format!("model {} doesn't exist", model.model_id))
+            })?;
+            if model.voxels.is_empty() {
+                return Ok(());
+            }
+
+            let name = name
+                .or(node.name())
+                .map_or_else(|| format!("matrix{}", matrices.len()), str::to_string);
+            matrices.push(matrix(name, model, parent, scene.palette()));
+            Ok(())
+        }
+    }
+}
+
+fn matrix(
+    name: String,
+    model: &dot_vox::Model,
+    (rotation, translation): (VoxRotation, IVec3),
+    palette: &[dot_vox::Color],
+) -> Matrix {
+    let size = IVec3::new(
+        model.size.x as i32,
+        model.size.y as i32,
+        model.size.z as i32,
+    );
+    // magicavoxel puts the voxel at half the size of a model on the translation, the center of
+    // a voxel is on a half so doubling keeps it whole
+    let place = |voxel: IVec3| {
+        let center = rotation.transform_ivec3(voxel * 2 + IVec3::ONE - (size / 2) * 2);
+        let magica = (center + translation * 2 - IVec3::ONE).div_euclid(IVec3::splat(2));
+        IVec3::new(magica.x, magica.z, magica.y)
+    };
+
+    let (first, last) = (place(IVec3::ZERO), place(size - IVec3::ONE));
+    let position = first.min(last);
+    let qb_size = ((first - last).abs() + IVec3::ONE).as_uvec3();
+
+    let index = |voxel: IVec3| {
+        let voxel = (voxel - position).as_uvec3();
+        (voxel.x + (voxel.y + voxel.z * qb_size.y) * qb_size.x) as usize
+    };
+    let mut colors = vec![[0; 4]; (qb_size.x * qb_size.y * qb_size.z) as usize];
+    for voxel in &model.voxels {
+        let position = IVec3::new(voxel.x as i32, voxel.y as i32, voxel.z as i32);
+        if position.cmpge(size).any() {
+            continue;
+        }
+        let color = palette[voxel.i as usize];
+        colors[index(place(position))] = [color.r, color.g, color.b, 255];
+    }
+
+    Matrix {
+        name,
+        position,
+        size: qb_size,
+        colors,
+    }
+}
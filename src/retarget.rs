@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::{
+    error::{warn, Error, Result},
+    humanoid::HumanBone,
+    rig::Skeleton,
+};
+
+/// Moves poses of the `source` skeleton onto `target`, matching bones by `Bone::human`.
+///
+/// Every matched bone gets the rotation its source bone has relative to the source rest pose, on
+/// top of its own rest pose. The hips also take over the movement of the source hips, scaled by
+/// the difference in hip height along `up`. Bones without a match keep their rest pose and
+/// follow their parent. Returns globals of `target` for every pose.
+pub fn retarget(
+    source: &Skeleton,
+    source_poses: &[Vec<Transform>],
+    target: &Skeleton,
+    up: Vec3,
+) -> Result<Vec<Vec<Transform>>> {
+    let source_bones = source
+        .bones
+        .iter()
+        .enumerate()
+        .filter_map(|(index, bone)| Some((bone.human?, index)))
+        .collect::<HashMap<_, _>>();
+    let matches = target
+        .bones
+        .iter()
+        .map(|bone| {
+            bone.human
+                .and_then(|human| source_bones.get(&human).copied())
+        })
+        .collect::<Vec<_>>();
+
+    if matches.iter().all(Option::is_none) {
+        return Err(Error::Unsupported(
+            "no bones of the input match the bones of the animation, map them with a bone preset \
+             or bone_map"
+                .to_string(),
+        ));
+    }
+    for (bone, matched) in target.bones.iter().zip(&matches) {
+        if let (Some(human), None) = (bone.human, matched) {
+            warn(format!("the animation has no {} to retarget", human));
+        }
+    }
+
+    let source_rest = source.globals(&source.rest_locals());
+    let target_rest = target.globals(&target.rest_locals());
+
+    let hips = target
+        .bones
+        .iter()
+        .position(|bone| bone.human == Some(HumanBone::Hips))
+        .and_then(|hips| Some((hips, matches[hips]?)));
+    let height_ratio = hips.map_or(1.0, |(target_hips, source_hips)| {
+        let source_height = source_rest[source_hips].translation.dot(up);
+        let target_height = target_rest[target_hips].translation.dot(up);
+        if source_height.abs() > f32::EPSILON {
+            target_height / source_height
+        } else {
+            1.0
+        }
+    });
+
+    Ok(source_poses
+        .iter()
+        .map(|pose| {
+            let mut globals: Vec<Transform> = Vec::with_capacity(target.bones.len());
+            for (bone, data) in target.bones.iter().enumerate() {
+                let parent = data.parent.map_or(data.base, |parent| globals[parent]);
+                let mut global = parent * data.rest;
+
+                if let Some(source_bone) = matches[bone] {
+                    let delta =
+                        pose[source_bone].rotation * source_rest[source_bone].rotation.inverse();
+                    global.rotation = delta * target_rest[bone].rotation;
+
+                    if hips.is_some_and(|(hips, _)| hips == bone) {
+                        let offset =
+                            pose[source_bone].translation - source_rest[source_bone].translation;
+                        global.translation = target_rest[bone].translation + offset * height_ratio;
+                    }
+                }
+                globals.push(global);
+            }
+            globals
+        })
+        .collect())
+}
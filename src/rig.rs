@@ -0,0 +1,461 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use gltf::Document;
+
+use crate::{
+    animation::AnimationSampler,
+    config::{BoneOverride, Color},
+    filter::{BoneFilter, BonePattern, FilteredSkeleton},
+    humanoid::HumanBone,
+    layers::{self, LayerRule, Side},
+    rotation::VoxRotation,
+    symmetry,
+    tiles::VoxelModel,
+    transform_from_gltf,
+    vox_tree::VoxNode,
+    VoxFrame, VoxScene, BONE_VOXEL_THICKNESS,
+};
+
+#[derive(Debug, Clone)]
+pub struct RigSettings {
+    /// Voxels per glTF unit.
+    pub scale: f32,
+    /// Whatever axis of a bone part is not the longest will be at least this thick, in voxels.
+    pub thickness: f32,
+    /// Layers to sort the bone parts into, see `layers::assign_layers`.
+    pub layers: Vec<LayerRule>,
+    /// Color of the bone parts.
+    pub color: [u8; 4],
+    /// Settings for the bones matching a pattern, later ones win.
+    pub bones: Vec<(BonePattern, BoneOverride)>,
+    pub filter: BoneFilter,
+    /// Build this side of the character and mirror it onto the other, see `symmetry::mirror_parts`.
+    pub mirror: Option<Side>,
+}
+
+impl Default for RigSettings {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            thickness: BONE_VOXEL_THICKNESS,
+            layers: layers::default_layers(),
+            color: [0, 0, 0, 255],
+            bones: vec![],
+            filter: BoneFilter::default(),
+            mirror: None,
+        }
+    }
+}
+
+impl RigSettings {
+    /// The overrides of every pattern that matches a name of `bone` merged together.
+    pub fn bone(&self, bone: &Bone) -> BoneOverride {
+        let names = bone.names();
+        let mut settings = BoneOverride::default();
+        for (pattern, overrides) in &self.bones {
+            if names.iter().any(|name| pattern.matches(name)) {
+                settings.merge(overrides);
+            }
+        }
+        settings
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Bone {
+    /// Index of the joint node in the glTF document.
+    pub node: usize,
+    pub name: Option<String>,
+    /// The bone of the canonical humanoid skeleton, set by `BoneMap::apply`.
+    pub human: Option<HumanBone>,
+    /// Index into `Skeleton::bones`, parents always come before their children.
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    pub rest: Transform,
+    /// Accumulated transform of the non-joint nodes above a root bone, identity for all others.
+    pub base: Transform,
+}
+
+#[derive(Debug, Clone)]
+pub struct Skeleton {
+    pub bones: Vec<Bone>,
+}
+
+impl Bone {
+    /// The canonical humanoid name, the glTF name, or `joint_` and the node index for unnamed
+    /// joints.
+    pub fn display_name(&self) -> String {
+        match (self.human, &self.name) {
+            (Some(human), _) => human.to_string(),
+            (None, Some(name)) => name.clone(),
+            (None, None) => format!("joint_{}", self.node),
+        }
+    }
+
+    /// The display name, and the glTF name when that's different. Patterns can match either.
+    pub fn names(&self) -> Vec<String> {
+        let mut names = vec![self.display_name()];
+        names.extend(self.name.clone().filter(|name| *name != names[0]));
+        names
+    }
+}
+
+impl Skeleton {
+    pub fn from_skin(document: &Document, skin: &gltf::Skin) -> Self {
+        let joints = skin.joints().map(|joint| joint.index()).collect::<Vec<_>>();
+
+        let mut parents = HashMap::new();
+        for node in document.nodes() {
+            for child in node.children() {
+                parents.insert(child.index(), node.index());
+            }
+        }
+
+        let mut skeleton = Skeleton { bones: vec![] };
+        for joint in skin.joints() {
+            let is_root = parents
+                .get(&joint.index())
+                .is_none_or(|parent| !joints.contains(parent));
+            if !is_root {
+                continue;
+            }
+
+            let mut base = Transform::IDENTITY;
+            let mut ancestor = parents.get(&joint.index());
+            while let Some(&index) = ancestor {
+                let node = document.nodes().nth(index).unwrap();
+                base = transform_from_gltf(node.transform()) * base;
+                ancestor = parents.get(&index);
+            }
+
+            skeleton.add_bone(joint, None, base, &joints);
+        }
+
+        skeleton
+    }
+
+    fn add_bone(
+        &mut self,
+        node: gltf::Node,
+        parent: Option<usize>,
+        base: Transform,
+        joints: &[usize],
+    ) -> usize {
+        let index = self.bones.len();
+        self.bones.push(Bone {
+            node: node.index(),
+            name: node.name().map(|name| name.to_string()),
+            human: None,
+            parent,
+            children: vec![],
+            rest: transform_from_gltf(node.transform()),
+            base,
+        });
+
+        for child in node.children() {
+            if !joints.contains(&child.index()) {
+                continue;
+            }
+            let child = self.add_bone(child, Some(index), Transform::IDENTITY, joints);
+            self.bones[index].children.push(child);
+        }
+
+        index
+    }
+
+    pub fn rest_locals(&self) -> Vec<Transform> {
+        self.bones.iter().map(|bone| bone.rest).collect()
+    }
+
+    pub fn sample_locals(&self, sampler: &AnimationSampler, time: f32) -> Vec<Transform> {
+        self.bones
+            .iter()
+            .map(|bone| sampler.sample(bone.node, bone.rest, time))
+            .collect()
+    }
+
+    pub fn globals(&self, locals: &[Transform]) -> Vec<Transform> {
+        let mut globals: Vec<Transform> = Vec::with_capacity(self.bones.len());
+        for (bone, local) in self.bones.iter().zip(locals) {
+            let parent = bone.parent.map_or(bone.base, |parent| globals[parent]);
+            globals.push(parent * *local);
+        }
+        globals
+    }
+}
+
+/// The box of voxels that represents a bone, spanning from its joint to the joints of its children.
+#[derive(Debug, Clone, Copy)]
+pub struct BonePart {
+    /// Center in rest pose, in voxels.
+    pub center: Vec3,
+    /// Size in voxels along the glTF axes.
+    pub size: UVec3,
+}
+
+impl BonePart {
+    pub fn from_rest(
+        skeleton: &Skeleton,
+        rest_globals: &[Transform],
+        bone: usize,
+        settings: &RigSettings,
+    ) -> Self {
+        let head = rest_globals[bone].translation * settings.scale;
+
+        let mut tails = skeleton.bones[bone]
+            .children
+            .iter()
+            .map(|child| rest_globals[*child].translation * settings.scale)
+            .collect::<Vec<_>>();
+        //  we just create a bone with length of 1 in the direction of the current joint
+        if tails.is_empty() {
+            tails.push(rest_globals[bone].transform_point(Vec3::Y) * settings.scale);
+        }
+
+        let (min, max) = tails.iter().fold((head, head), |(min, max), tail| {
+            (min.min(*tail), max.max(*tail))
+        });
+
+        let thickness = settings
+            .bone(&skeleton.bones[bone])
+            .thickness
+            .unwrap_or(settings.thickness);
+        let size = (max - min)
+            .max(Vec3::splat(thickness))
+            .round()
+            .as_uvec3()
+            .max(UVec3::ONE);
+
+        BonePart {
+            center: (min + max) / 2.0,
+            size,
+        }
+    }
+}
+
+/// Snapped transform of every bone part relative to the part of its parent bone.
+///
+/// Rotations are snapped in global space and made relative afterwards, so the rounding of one
+/// bone doesn't accumulate into its children.
+pub fn part_locals(
+    skeleton: &Skeleton,
+    parts: &[BonePart],
+    rest_globals: &[Transform],
+    pose_globals: &[Transform],
+    settings: &RigSettings,
+) -> Vec<VoxFrame> {
+    let snapped = (0..skeleton.bones.len())
+        .map(|bone| {
+            let rest = rest_globals[bone];
+            let pose = pose_globals[bone];
+
+            // where the center of the part ends up when it follows the joint
+            let local_center = rest
+                .compute_affine()
+                .inverse()
+                .transform_point3(parts[bone].center / settings.scale);
+            let center = pose.compute_affine().transform_point3(local_center) * settings.scale;
+
+            let rotation = VoxRotation::from_quat(pose.rotation * rest.rotation.inverse());
+            (center, rotation)
+        })
+        .collect::<Vec<_>>();
+
+    skeleton
+        .bones
+        .iter()
+        .enumerate()
+        .map(|(bone, data)| {
+            let (center, rotation) = snapped[bone];
+            match data.parent {
+                None => VoxFrame {
+                    translation: center,
+                    rotation,
+                },
+                Some(parent) => {
+                    let (parent_center, parent_rotation) = snapped[parent];
+                    let inverse = parent_rotation.inverse();
+                    VoxFrame {
+                        translation: inverse * (center - parent_center),
+                        rotation: inverse * rotation,
+                    }
+                }
+            }
+        })
+        .collect()
+}
+
+/// Writes the skeleton as nested voxel parts, one keyframe per entry of `pose_globals`.
+///
+/// Returns the transform of every root bone.
+pub fn write_rig(
+    scene: &mut VoxScene,
+    skeleton: &Skeleton,
+    pose_globals: &[Vec<Transform>],
+    settings: &RigSettings,
+) -> Vec<VoxNode> {
+    let rest_globals = skeleton.globals(&skeleton.rest_locals());
+    let bone_settings = skeleton
+        .bones
+        .iter()
+        .map(|bone| {
+            let mut bone = settings.bone(bone);
+            bone.color.get_or_insert(Color(settings.color));
+            bone
+        })
+        .collect::<Vec<_>>();
+
+    // a bone that is filtered out moves its children to its parent, and with `collapse` its voxels
+    let keep = skeleton
+        .bones
+        .iter()
+        .zip(&bone_settings)
+        .map(|(bone, settings_of_bone)| match settings_of_bone.exclude {
+            Some(exclude) => !exclude,
+            None => settings.filter.keeps(bone),
+        })
+        .collect::<Vec<_>>();
+    let filtered = FilteredSkeleton::new(skeleton, &keep, settings.filter.collapse);
+
+    let mut pieces = vec![vec![]; filtered.skeleton.bones.len()];
+    for (bone, bone_settings) in bone_settings.iter().enumerate() {
+        if let Some(owner) = filtered.owners[bone] {
+            let part = BonePart::from_rest(skeleton, &rest_globals, bone, settings);
+            pieces[owner].push((part, bone_settings.color.unwrap().0));
+        }
+    }
+    let mut models = pieces
+        .iter()
+        .map(|pieces| part_model(scene, pieces))
+        .collect::<Vec<_>>();
+
+    // from here on only the kept bones matter
+    let skeleton = &filtered.skeleton;
+    let select = |globals: &[Transform]| {
+        filtered
+            .bones
+            .iter()
+            .map(|bone| globals[*bone])
+            .collect::<Vec<_>>()
+    };
+    let rest_globals = select(&rest_globals);
+    if let Some(side) = settings.mirror {
+        symmetry::mirror_parts(
+            scene.basis(),
+            skeleton,
+            &rest_globals,
+            &mut models,
+            side,
+            settings.scale,
+        );
+    }
+    let parts = models.iter().map(|(part, _)| *part).collect::<Vec<_>>();
+    let frames = pose_globals
+        .iter()
+        .map(|pose| part_locals(skeleton, &parts, &rest_globals, &select(pose), settings))
+        .collect::<Vec<_>>();
+
+    // only the layers that got a bone are added to the scene
+    let overrides = filtered
+        .bones
+        .iter()
+        .map(|bone| bone_settings[*bone].layer.clone())
+        .collect::<Vec<_>>();
+    let mut layer_ids = HashMap::new();
+    let layers = layers::assign_layers(skeleton, &settings.layers, &overrides)
+        .into_iter()
+        .map(|name| {
+            let name = name?;
+            Some(
+                *layer_ids
+                    .entry(name)
+                    .or_insert_with_key(|name| scene.add_layer(name)),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    fn write_bone(
+        scene: &mut VoxScene,
+        skeleton: &Skeleton,
+        models: &[(BonePart, VoxelModel)],
+        frames: &[Vec<VoxFrame>],
+        layers: &[Option<u32>],
+        bone: usize,
+    ) -> VoxNode {
+        let name = skeleton.bones[bone].display_name();
+        let mut part =
+            scene.model_instance(Some(&format!("{}_part", name)), models[bone].1.clone());
+        if let Some(layer) = layers[bone] {
+            part.set_layer(layer);
+        }
+
+        let mut children = vec![part];
+        for child in &skeleton.bones[bone].children {
+            children.push(write_bone(scene, skeleton, models, frames, layers, *child));
+        }
+        let group = scene.group(Some(&format!("{}_group", name)), children);
+
+        let keyframes = frames.iter().map(|frame| frame[bone]).collect::<Vec<_>>();
+        scene.transform(Some(&name), &keyframes, group)
+    }
+
+    skeleton
+        .bones
+        .iter()
+        .enumerate()
+        .filter(|(_, bone)| bone.parent.is_none())
+        .map(|(bone, _)| write_bone(scene, skeleton, &models, &frames, &layers, bone))
+        .collect()
+}
+
+/// The part around a number of boxes and its voxels, the first box is the bone itself and wins
+/// where boxes overlap.
+fn part_model(scene: &mut VoxScene, pieces: &[(BonePart, [u8; 4])]) -> (BonePart, VoxelModel) {
+    let min = |part: &BonePart| part.center - part.size.as_vec3() / 2.0;
+    let corner = pieces
+        .iter()
+        .fold(Vec3::INFINITY, |corner, (part, _)| corner.min(min(part)));
+    let offsets = pieces
+        .iter()
+        .map(|(part, _)| (min(part) - corner).round().as_uvec3())
+        .collect::<Vec<_>>();
+    let size = pieces
+        .iter()
+        .zip(&offsets)
+        .fold(UVec3::ONE, |size, ((part, _), offset)| {
+            size.max(*offset + part.size)
+        });
+
+    let index =
+        |position: UVec3| (position.x + (position.y + position.z * size.y) * size.x) as usize;
+    let mut grid = vec![None; (size.x * size.y * size.z) as usize];
+    for ((part, color), offset) in pieces.iter().zip(&offsets) {
+        let color_index = scene.color_index(*color);
+        for z in 0..part.size.z {
+            for y in 0..part.size.y {
+                for x in 0..part.size.x {
+                    grid[index(*offset + UVec3::new(x, y, z))].get_or_insert(color_index);
+                }
+            }
+        }
+    }
+
+    let mut voxels = vec![];
+    for z in 0..size.z {
+        for y in 0..size.y {
+            for x in 0..size.x {
+                let position = UVec3::new(x, y, z);
+                if let Some(color_index) = grid[index(position)] {
+                    voxels.push((position, color_index));
+                }
+            }
+        }
+    }
+
+    let part = BonePart {
+        center: corner + size.as_vec3() / 2.0,
+        size,
+    };
+    (part, scene.voxel_model(size, voxels))
+}
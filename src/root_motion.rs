@@ -0,0 +1,62 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::rig::Skeleton;
+
+/// What to do with the horizontal movement of the root bone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RootMotion {
+    /// Export the movement as-is, the rig walks out of its model space.
+    #[default]
+    Keep,
+    /// Drop the movement so the rig stays in place.
+    InPlace,
+    /// Keep the rig in place and move a separate root transform instead.
+    Extract,
+}
+
+impl RootMotion {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "keep" => Some(RootMotion::Keep),
+            "in-place" | "inplace" => Some(RootMotion::InPlace),
+            "extract" => Some(RootMotion::Extract),
+            _ => None,
+        }
+    }
+}
+
+/// The bone that carries the root motion, `name` falls back to the first root bone.
+pub fn root_bone(skeleton: &Skeleton, name: Option<&str>) -> Option<usize> {
+    match name {
+        Some(name) => skeleton
+            .bones
+            .iter()
+            .position(|bone| bone.names().iter().any(|bone_name| bone_name == name)),
+        None => skeleton.bones.iter().position(|bone| bone.parent.is_none()),
+    }
+}
+
+/// Removes the horizontal movement of `root` from every pose, relative to the first one.
+/// Horizontal is everything perpendicular to `up`.
+///
+/// Every bone is shifted by the same amount so the whole body stays in place. Returns the
+/// removed offset of every frame, in glTF units.
+pub fn extract_root_motion(poses: &mut [Vec<Transform>], root: usize, up: Vec3) -> Vec<Vec3> {
+    let Some(start) = poses.first().map(|pose| pose[root].translation) else {
+        return vec![];
+    };
+
+    poses
+        .iter_mut()
+        .map(|pose| {
+            let delta = pose[root].translation - start;
+            let offset = delta - up * delta.dot(up);
+            for global in pose.iter_mut() {
+                global.translation -= offset;
+            }
+            offset
+        })
+        .collect()
+}
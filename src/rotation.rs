@@ -0,0 +1,295 @@
+use std::ops::Mul;
+
+use bevy::prelude::*;
+
+const PERMUTATIONS: [[u8; 3]; 6] = [
+    [0, 1, 2],
+    [0, 2, 1],
+    [1, 0, 2],
+    [1, 2, 0],
+    [2, 0, 1],
+    [2, 1, 0],
+];
+
+/// One of the 48 signed permutation matrices MagicaVoxel can store in a `_r` byte: the 24
+/// rotations and their 24 mirror images.
+///
+/// Unlike a `Quat` this can represent reflections, and it is always orthogonal by construction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VoxRotation {
+    /// Column of the non-zero entry of every row.
+    columns: [u8; 3],
+    /// Whether the non-zero entry of every row is -1.
+    negative: [bool; 3],
+}
+
+impl Default for VoxRotation {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl VoxRotation {
+    pub const IDENTITY: Self = Self {
+        columns: [0, 1, 2],
+        negative: [false; 3],
+    };
+
+    /// Mirrors along the X axis, e.g. to turn a left limb into a right one.
+    pub const MIRROR_X: Self = Self {
+        columns: [0, 1, 2],
+        negative: [true, false, false],
+    };
+
+    /// All 48 signed permutation matrices.
+    pub fn all() -> impl Iterator<Item = Self> {
+        PERMUTATIONS.into_iter().flat_map(|columns| {
+            (0..8u8).map(move |signs| Self {
+                columns,
+                negative: [signs & 1 != 0, signs & 2 != 0, signs & 4 != 0],
+            })
+        })
+    }
+
+    /// The signed permutation matrix closest to `mat`.
+    ///
+    /// Every row gets a different column, so unlike snapping each axis on its own the result
+    /// never has repeated axes, even for rotations close to 45 degrees.
+    pub fn from_mat3(mat: Mat3) -> Self {
+        let rows = [mat.row(0), mat.row(1), mat.row(2)];
+
+        let columns = PERMUTATIONS
+            .into_iter()
+            .max_by(|a, b| {
+                let score = |p: &[u8; 3]| -> f32 {
+                    (0..3).map(|row| rows[row][p[row] as usize].abs()).sum()
+                };
+                score(a).total_cmp(&score(b))
+            })
+            .unwrap();
+
+        Self {
+            columns,
+            negative: [0, 1, 2].map(|row| rows[row][columns[row] as usize] < 0.0),
+        }
+    }
+
+    pub fn from_quat(q: Quat) -> Self {
+        Self::from_mat3(Mat3::from_quat(q))
+    }
+
+    pub fn to_mat3(self) -> Mat3 {
+        let row = |row: usize| {
+            let mut v = Vec3::ZERO;
+            v[self.columns[row] as usize] = self.sign(row);
+            v
+        };
+        Mat3::from_cols(row(0), row(1), row(2)).transpose()
+    }
+
+    /// `None` for reflections, a quaternion can only describe proper rotations.
+    pub fn to_quat(self) -> Option<Quat> {
+        if self.is_reflection() {
+            return None;
+        }
+        Some(Quat::from_mat3(&self.to_mat3()))
+    }
+
+    pub fn is_reflection(self) -> bool {
+        self.to_mat3().determinant() < 0.0
+    }
+
+    pub fn inverse(self) -> Self {
+        // signed permutation matrices are orthogonal, so the inverse is the transpose
+        let mut inverse = Self::IDENTITY;
+        for row in 0..3 {
+            let column = self.columns[row] as usize;
+            inverse.columns[column] = row as u8;
+            inverse.negative[column] = self.negative[row];
+        }
+        inverse
+    }
+
+    /// Row and column of the non-zero entry of `row`, and whether it's negative.
+    pub fn entry(self, row: usize) -> (usize, bool) {
+        (self.columns[row] as usize, self.negative[row])
+    }
+
+    pub fn transform_ivec3(self, v: IVec3) -> IVec3 {
+        IVec3::from_array([0, 1, 2].map(|row| {
+            let value = v[self.columns[row] as usize];
+            if self.negative[row] {
+                -value
+            } else {
+                value
+            }
+        }))
+    }
+
+    fn sign(self, row: usize) -> f32 {
+        if self.negative[row] {
+            -1.0
+        } else {
+            1.0
+        }
+    }
+}
+
+impl Mul for VoxRotation {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        // row i of the product is row `columns[i]` of `rhs`, scaled by the sign of row i
+        let mut product = Self::IDENTITY;
+        for row in 0..3 {
+            let (column, negative) = rhs.entry(self.columns[row] as usize);
+            product.columns[row] = column as u8;
+            product.negative[row] = negative != self.negative[row];
+        }
+        product
+    }
+}
+
+impl Mul<Vec3> for VoxRotation {
+    type Output = Vec3;
+
+    fn mul(self, rhs: Vec3) -> Vec3 {
+        Vec3::from_array([0, 1, 2].map(|row| rhs[self.columns[row] as usize] * self.sign(row)))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RotationError {
+    /// Bit 7 of a rotation byte is unused and has to be clear.
+    ReservedBit(u8),
+    /// A row index of 3, only 0, 1 and 2 exist.
+    InvalidIndex(u8),
+    /// Both encoded rows point at the same column, so the matrix isn't a permutation.
+    RepeatedIndex(u8),
+    /// The byte is a valid mirror matrix, which can't be represented as a quaternion.
+    Reflection(u8),
+}
+
+impl std::fmt::Display for RotationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RotationError::ReservedBit(byte) => write!(f, "rotation {} has bit 7 set", byte),
+            RotationError::InvalidIndex(byte) => {
+                write!(f, "rotation {} has a row index out of range", byte)
+            }
+            RotationError::RepeatedIndex(byte) => {
+                write!(f, "rotation {} uses the same column twice", byte)
+            }
+            RotationError::Reflection(byte) => write!(f, "rotation {} is a reflection", byte),
+        }
+    }
+}
+
+impl VoxRotation {
+    /// Encodes the matrix as a MagicaVoxel `_r` byte, row-major.
+    pub fn to_byte(self) -> u8 {
+        self.columns[0]
+            | (self.columns[1] << 2)
+            | ((self.negative[0] as u8) << 4)
+            | ((self.negative[1] as u8) << 5)
+            | ((self.negative[2] as u8) << 6)
+    }
+
+    /// Decodes a MagicaVoxel `_r` byte, reflections included.
+    pub fn from_byte(byte: u8) -> Result<Self, RotationError> {
+        if byte & 0b1000_0000 != 0 {
+            return Err(RotationError::ReservedBit(byte));
+        }
+
+        let first = byte & 0b11;
+        let second = (byte >> 2) & 0b11;
+        if first > 2 || second > 2 {
+            return Err(RotationError::InvalidIndex(byte));
+        }
+        if first == second {
+            return Err(RotationError::RepeatedIndex(byte));
+        }
+
+        Ok(Self {
+            // the third row takes the column that's left
+            columns: [first, second, 3 - first - second],
+            negative: [4, 5, 6].map(|bit| byte & (1 << bit) != 0),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bytes_round_trip() {
+        let rotations = VoxRotation::all().collect::<Vec<_>>();
+        assert_eq!(rotations.len(), 48);
+
+        for rotation in rotations {
+            let byte = rotation.to_byte();
+            assert_eq!(byte & 0b1000_0000, 0);
+            assert_eq!(VoxRotation::from_byte(byte), Ok(rotation));
+        }
+    }
+
+    #[test]
+    fn quats_round_trip_through_bytes() {
+        // a small tilt, so the quaternions have to be snapped before they're encoded
+        let tilt = Quat::from_euler(EulerRot::XYZ, 0.1, -0.05, 0.08);
+        for rotation in VoxRotation::all().filter(|rotation| !rotation.is_reflection()) {
+            let q = rotation.to_quat().unwrap() * tilt;
+            let snapped = VoxRotation::from_quat(q);
+            assert_eq!(snapped, rotation);
+
+            let decoded = VoxRotation::from_byte(snapped.to_byte())
+                .unwrap()
+                .to_quat()
+                .unwrap();
+            let expected = snapped.to_quat().unwrap();
+            assert!(
+                decoded.dot(expected).abs() > 0.9999,
+                "{:?} decoded as {:?}",
+                expected,
+                decoded
+            );
+        }
+    }
+
+    #[test]
+    fn reflections_have_no_quat() {
+        assert!(VoxRotation::MIRROR_X.is_reflection());
+        assert_eq!(VoxRotation::MIRROR_X.to_quat(), None);
+        assert_eq!(
+            VoxRotation::all()
+                .filter(|rotation| rotation.is_reflection())
+                .count(),
+            24
+        );
+    }
+
+    #[test]
+    fn decodes_bytes() {
+        let cases = [
+            (0b0000_0100, Ok(VoxRotation::IDENTITY)),
+            (0b0001_0100, Ok(VoxRotation::MIRROR_X)),
+            (0b1000_0100, Err(RotationError::ReservedBit(0b1000_0100))),
+            (0b1111_1111, Err(RotationError::ReservedBit(0b1111_1111))),
+            (0b0000_0111, Err(RotationError::InvalidIndex(0b0000_0111))),
+            (0b0000_1101, Err(RotationError::InvalidIndex(0b0000_1101))),
+            (0b0000_1111, Err(RotationError::InvalidIndex(0b0000_1111))),
+            (0b0000_0000, Err(RotationError::RepeatedIndex(0b0000_0000))),
+            (0b0000_0101, Err(RotationError::RepeatedIndex(0b0000_0101))),
+            (0b0111_1010, Err(RotationError::RepeatedIndex(0b0111_1010))),
+        ];
+        for (byte, expected) in cases {
+            assert_eq!(
+                VoxRotation::from_byte(byte),
+                expected,
+                "byte {:#010b}",
+                byte
+            );
+        }
+    }
+}
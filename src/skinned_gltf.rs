@@ -0,0 +1,426 @@
+use std::{collections::HashMap, path::Path};
+
+use bevy::prelude::*;
+use gltf::{animation::util::ReadOutputs, buffer, Document};
+use serde_json::json;
+
+use crate::{
+    error::{warn, Error, Result},
+    gltf_writer::{Channel, ChannelValues, GltfWriter},
+    mesh::{model_point, MeshSettings, VoxelMesh},
+    rig::Skeleton,
+    skinning::{self, SkinnedMesh},
+    vox_gltf::rest_models,
+    VoxScene,
+};
+
+/// Cells along the longest side of the source mesh, for finding the closest vertex.
+const GRID_CELLS: f32 = 32.0;
+
+/// Writes the models of a scene as a single mesh skinned to the skeleton of the input, so the
+/// animations of the input play on the voxels.
+///
+/// `scene` has to be built from the rest pose. Every voxel takes the joints and weights of the
+/// closest vertex of the skinned mesh, and faces only merge with faces of the same weights. The
+/// joints keep their nodes, order and inverse bind matrices, so the `JOINTS_0` of the input
+/// still apply.
+pub fn write_skinned_gltf(
+    scene: &VoxScene,
+    settings: &MeshSettings,
+    document: &Document,
+    buffers: &[buffer::Data],
+    skin: &gltf::Skin,
+    skeleton: &Skeleton,
+    path: &Path,
+) -> Result<()> {
+    let source = SkinnedMesh::from_skin(document, buffers, skin)?;
+    if source.triangles.is_empty() {
+        return Err(Error::Unsupported(format!(
+            "no skinned triangle mesh uses skin {:?}",
+            skin.name()
+        )));
+    }
+    let rest_globals = skeleton.globals(&skeleton.rest_locals());
+    let joint_matrices =
+        skinning::joint_matrices(skin, buffers, skeleton, &rest_globals, source.global);
+    let grid = VertexGrid::new(source.pose(&joint_matrices));
+
+    // every distinct set of joints and weights, with the matrix that takes a vertex bound to it
+    // from rest pose back to bind pose
+    let mut influences = vec![];
+    let mut influence_ids = HashMap::new();
+    let mut mesh = SkinnedVoxels::default();
+
+    for (_, model_id, global) in rest_models(scene.root(), Mat4::IDENTITY, settings) {
+        let model = scene
+            .models()
+            .get(model_id as usize)
+            .ok_or_else(|| Error::InvalidVox(format!("model {} doesn't exist", model_id)))?;
+
+        let mut groups = HashMap::new();
+        for voxel in &model.voxels {
+            let position = IVec3::new(voxel.x as i32, voxel.y as i32, voxel.z as i32);
+            let center =
+                global.transform_point3(model_point(model, position.as_vec3() + 0.5, settings));
+            let Some(vertex) = grid.nearest(center) else {
+                continue;
+            };
+            let influence = influence(source.joints[vertex], source.weights[vertex]);
+            let group = *influence_ids.entry(influence).or_insert_with(|| {
+                let bind = bind_matrix(source.global, &joint_matrices, influence);
+                influences.push((influence, bind));
+                influences.len() as u32 - 1
+            });
+            groups.insert(position, group);
+        }
+
+        let Some(voxels) = VoxelMesh::from_model_grouped(model, settings, |position| {
+            groups.get(&position).copied().unwrap_or(0)
+        }) else {
+            continue;
+        };
+        mesh.add(&voxels, global, &influences, scene.palette());
+    }
+    if mesh.positions.is_empty() {
+        return Err(Error::Unsupported(
+            "the scene has no voxels to mesh".to_string(),
+        ));
+    }
+
+    let mut writer = GltfWriter::default();
+    let (nodes, roots) = write_skeleton(&mut writer, document, skeleton);
+
+    let reader = skin.reader(|buffer| Some(&buffers[buffer.index()]));
+    let mut inverse_bind_matrices = reader
+        .read_inverse_bind_matrices()
+        .map(|matrices| {
+            matrices
+                .map(|matrix| Mat4::from_cols_array_2d(&matrix))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    let joints = skin
+        .joints()
+        .map(|joint| nodes[&joint.index()])
+        .collect::<Vec<_>>();
+    inverse_bind_matrices.resize(joints.len(), Mat4::IDENTITY);
+    let skin_index = writer.skin(&joints, &inverse_bind_matrices);
+
+    let attributes = json!({
+        "POSITION": writer.vec3s(&mesh.positions),
+        "NORMAL": writer.vec3s(&mesh.normals),
+        "COLOR_0": writer.vec4s(&mesh.colors),
+        "JOINTS_0": writer.joints(&mesh.joints),
+        "WEIGHTS_0": writer.vec4s(&mesh.weights),
+    });
+    let indices = writer.indices(&mesh.indices);
+    let name = skin.name().unwrap_or("voxels");
+    let mesh_index = writer.mesh(Some(name), attributes, indices);
+    let mesh_node = writer.node(json!({
+        "name": name,
+        "mesh": mesh_index,
+        "skin": skin_index,
+    }));
+
+    for (index, animation) in document.animations().enumerate() {
+        let channels = animation_channels(&animation, buffers, &nodes);
+        if !channels.is_empty() {
+            let name = animation
+                .name()
+                .map_or_else(|| format!("animation{}", index), str::to_string);
+            writer.animation(&name, &channels);
+        }
+    }
+
+    let mut scene_roots = vec![mesh_node];
+    scene_roots.extend(roots);
+    writer.save(path, &scene_roots)
+}
+
+/// The vertices of every model merged into one list, in bind pose.
+#[derive(Debug, Default)]
+struct SkinnedVoxels {
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    colors: Vec<Vec4>,
+    joints: Vec<[u16; 4]>,
+    weights: Vec<Vec4>,
+    indices: Vec<u32>,
+}
+
+impl SkinnedVoxels {
+    /// Adds a model placed at `global`, its groups index into `influences`.
+    fn add(
+        &mut self,
+        mesh: &VoxelMesh,
+        global: Mat4,
+        influences: &[(Influence, Mat4)],
+        palette: &[dot_vox::Color],
+    ) {
+        let first = self.positions.len() as u32;
+        for (i, group) in mesh.groups.iter().enumerate() {
+            let ((joints, weights), bind) = influences[*group as usize];
+            let matrix = bind * global;
+            self.positions
+                .push(matrix.transform_point3(mesh.positions[i]));
+            // normals go through the inverse transpose so they stay perpendicular
+            let normal_matrix = Mat3::from_mat4(matrix).inverse().transpose();
+            self.normals
+                .push((normal_matrix * mesh.normals[i]).normalize_or_zero());
+            self.joints.push(joints);
+            self.weights
+                .push(Vec4::from_array(weights.map(f32::from_bits)));
+        }
+        self.colors.extend(mesh.vertex_colors(palette));
+
+        // a mirroring transform turns the triangles inside out
+        for triangle in mesh.indices.chunks(3) {
+            let [a, b, c] = [0, 1, 2].map(|corner| triangle[corner] + first);
+            let (_, bind) = influences[mesh.groups[triangle[0] as usize] as usize];
+            if (bind * global).determinant() < 0.0 {
+                self.indices.extend([a, c, b]);
+            } else {
+                self.indices.extend([a, b, c]);
+            }
+        }
+    }
+}
+
+/// Joints and weights of a vertex, the weights as bits so they can be compared and hashed.
+type Influence = ([u16; 4], [u32; 4]);
+
+/// The joints and normalized weights of a vertex, the joints without weight set to 0.
+fn influence(joints: [u16; 4], weights: [f32; 4]) -> Influence {
+    let total = weights.iter().filter(|weight| **weight > 0.0).sum::<f32>();
+    if total <= 0.0 {
+        return ([0; 4], [1.0f32.to_bits(), 0, 0, 0]);
+    }
+    let mut joints = joints;
+    let mut bits = [0; 4];
+    for i in 0..4 {
+        if weights[i] > 0.0 {
+            bits[i] = (weights[i] / total).to_bits();
+        } else {
+            joints[i] = 0;
+        }
+    }
+    (joints, bits)
+}
+
+/// The inverse of the blended skinning matrix in rest pose placed by the mesh node at
+/// `mesh_global`, which takes a position in rest pose to where it has to be in bind pose to end
+/// up there again.
+fn bind_matrix(mesh_global: Mat4, joint_matrices: &[Mat4], (joints, weights): Influence) -> Mat4 {
+    let mut blended = Mat4::ZERO;
+    for (joint, weight) in joints.iter().zip(weights) {
+        let weight = f32::from_bits(weight);
+        if weight > 0.0 {
+            let matrix = joint_matrices
+                .get(*joint as usize)
+                .copied()
+                .unwrap_or(Mat4::IDENTITY);
+            blended += matrix * weight;
+        }
+    }
+    let blended = mesh_global * blended;
+    if blended.determinant().abs() <= f32::EPSILON {
+        return Mat4::IDENTITY;
+    }
+    blended.inverse()
+}
+
+/// Adds a node for every bone with its rest transform and glTF name. Root bones below nodes
+/// that aren't joints get a node with the transform of those.
+///
+/// Returns the new node of every joint node of the input, and the nodes to put in the scene.
+fn write_skeleton(
+    writer: &mut GltfWriter,
+    document: &Document,
+    skeleton: &Skeleton,
+) -> (HashMap<usize, usize>, Vec<usize>) {
+    fn write_bone(
+        writer: &mut GltfWriter,
+        skeleton: &Skeleton,
+        bone: usize,
+        nodes: &mut HashMap<usize, usize>,
+    ) -> usize {
+        let children = skeleton.bones[bone]
+            .children
+            .iter()
+            .map(|child| write_bone(writer, skeleton, *child, nodes))
+            .collect::<Vec<_>>();
+        let data = &skeleton.bones[bone];
+        let mut node = transform_node(data.rest);
+        if let Some(name) = &data.name {
+            node["name"] = json!(name);
+        }
+        if !children.is_empty() {
+            node["children"] = json!(children);
+        }
+        let index = writer.node(node);
+        nodes.insert(data.node, index);
+        index
+    }
+
+    let mut nodes = HashMap::new();
+    let mut roots = vec![];
+    for (bone, data) in skeleton.bones.iter().enumerate() {
+        if data.parent.is_some() {
+            continue;
+        }
+        let index = write_bone(writer, skeleton, bone, &mut nodes);
+        if data.base == Transform::IDENTITY {
+            roots.push(index);
+            continue;
+        }
+
+        let mut node = transform_node(data.base);
+        node["children"] = json!([index]);
+        let parent = document
+            .nodes()
+            .find(|node| node.children().any(|child| child.index() == data.node));
+        if let Some(name) = parent.as_ref().and_then(|parent| parent.name()) {
+            node["name"] = json!(name);
+        }
+        roots.push(writer.node(node));
+    }
+    (nodes, roots)
+}
+
+fn transform_node(transform: Transform) -> serde_json::Value {
+    let mut node = json!({
+        "translation": transform.translation.to_array(),
+        "rotation": transform.rotation.to_array(),
+    });
+    if transform.scale != Vec3::ONE {
+        node["scale"] = json!(transform.scale.to_array());
+    }
+    node
+}
+
+/// The channels of `animation` that move a joint, retargeted onto the new joint nodes.
+fn animation_channels(
+    animation: &gltf::Animation,
+    buffers: &[buffer::Data],
+    nodes: &HashMap<usize, usize>,
+) -> Vec<Channel> {
+    let mut channels = vec![];
+    for channel in animation.channels() {
+        let Some(node) = nodes.get(&channel.target().node().index()) else {
+            continue;
+        };
+        let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+        let (Some(inputs), Some(outputs)) = (reader.read_inputs(), reader.read_outputs()) else {
+            warn(format!(
+                "skipping unreadable channel targeting node {}",
+                channel.target().node().index()
+            ));
+            continue;
+        };
+        let values = match outputs {
+            ReadOutputs::Translations(values) => {
+                ChannelValues::Translation(values.map(Vec3::from).collect())
+            }
+            ReadOutputs::Rotations(values) => {
+                ChannelValues::Rotation(values.into_f32().map(Quat::from_array).collect())
+            }
+            ReadOutputs::Scales(values) => ChannelValues::Scale(values.map(Vec3::from).collect()),
+            // the voxels have no morph targets
+            ReadOutputs::MorphTargetWeights(_) => continue,
+        };
+        channels.push(Channel {
+            node: *node,
+            times: inputs.collect(),
+            values,
+            interpolation: channel.sampler().interpolation(),
+        });
+    }
+    channels
+}
+
+/// The vertices of the source mesh sorted into cells, to find the closest one to a point.
+struct VertexGrid {
+    positions: Vec<Vec3>,
+    cell_size: f32,
+    cells: HashMap<IVec3, Vec<usize>>,
+    // range of the cells that hold a vertex
+    min: IVec3,
+    max: IVec3,
+}
+
+impl VertexGrid {
+    fn new(positions: Vec<Vec3>) -> Self {
+        let (min, max) = positions.iter().fold(
+            (Vec3::INFINITY, Vec3::NEG_INFINITY),
+            |(min, max), position| (min.min(*position), max.max(*position)),
+        );
+        let cell_size = ((max - min).max_element() / GRID_CELLS).max(f32::EPSILON);
+
+        let mut grid = Self {
+            positions,
+            cell_size,
+            cells: HashMap::new(),
+            min: IVec3::MAX,
+            max: IVec3::MIN,
+        };
+        for (index, position) in grid.positions.iter().enumerate() {
+            let cell = grid.cell(*position);
+            grid.cells.entry(cell).or_default().push(index);
+            grid.min = grid.min.min(cell);
+            grid.max = grid.max.max(cell);
+        }
+        grid
+    }
+
+    fn cell(&self, position: Vec3) -> IVec3 {
+        (position / self.cell_size).floor().as_ivec3()
+    }
+
+    /// Searches rings of cells around the point, until no closer vertex can be in the next ring.
+    fn nearest(&self, point: Vec3) -> Option<usize> {
+        if self.positions.is_empty() {
+            return None;
+        }
+        let center = self.cell(point);
+        let last_ring = (center - self.min)
+            .abs()
+            .max((self.max - center).abs())
+            .max_element();
+
+        let mut best: Option<(usize, f32)> = None;
+        for ring in 0..=last_ring {
+            // every vertex in this ring is at least this far away
+            let closest = (ring - 1).max(0) as f32 * self.cell_size;
+            if best.is_some_and(|(_, distance)| distance <= closest) {
+                break;
+            }
+            // far from the mesh there are more cells to look at than vertices
+            if (2 * ring as usize + 1).pow(3) > self.positions.len() {
+                return (0..self.positions.len()).min_by(|a, b| {
+                    let distance = |vertex: &usize| self.positions[*vertex].distance(point);
+                    distance(a).total_cmp(&distance(b))
+                });
+            }
+            for z in -ring..=ring {
+                for y in -ring..=ring {
+                    for x in -ring..=ring {
+                        let offset = IVec3::new(x, y, z);
+                        if offset.abs().max_element() != ring {
+                            continue;
+                        }
+                        let Some(vertices) = self.cells.get(&(center + offset)) else {
+                            continue;
+                        };
+                        for vertex in vertices {
+                            let distance = self.positions[*vertex].distance(point);
+                            if best.is_none_or(|(_, best)| distance < best) {
+                                best = Some((*vertex, distance));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        best.map(|(vertex, _)| vertex)
+    }
+}
@@ -0,0 +1,269 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use gltf::{buffer, image, Document};
+
+use crate::{
+    error::{warn, Error, Result},
+    rig::Skeleton,
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct MaterialColor {
+    pub factor: [f32; 4],
+    /// Index into the images returned by `gltf::import`.
+    pub texture: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Triangle {
+    pub indices: [u32; 3],
+    pub material: usize,
+}
+
+/// All skinned primitives of the meshes bound to a skin, merged into a single vertex list.
+#[derive(Debug, Clone, Default)]
+pub struct SkinnedMesh {
+    pub positions: Vec<Vec3>,
+    pub uvs: Vec<Vec2>,
+    /// Indices into the joints of the skin, not into `Skeleton::bones`.
+    pub joints: Vec<[u16; 4]>,
+    pub weights: Vec<[f32; 4]>,
+    pub triangles: Vec<Triangle>,
+    pub materials: Vec<MaterialColor>,
+    /// Global transform of the first mesh node bound to the skin, the joint matrices are
+    /// relative to it.
+    pub global: Mat4,
+}
+
+impl SkinnedMesh {
+    pub fn from_skin(
+        document: &Document,
+        buffers: &[buffer::Data],
+        skin: &gltf::Skin,
+    ) -> Result<Self> {
+        let mut mesh = SkinnedMesh::default();
+        let mut global = None;
+
+        for node in document.nodes() {
+            let (Some(node_mesh), Some(node_skin)) = (node.mesh(), node.skin()) else {
+                continue;
+            };
+            if node_skin.index() != skin.index() {
+                continue;
+            }
+            global.get_or_insert_with(|| node_global(document, node.index()));
+
+            for primitive in node_mesh.primitives() {
+                if primitive.mode() != gltf::mesh::Mode::Triangles {
+                    warn(format!(
+                        "skipping non-triangle primitive of {:?}",
+                        node_mesh.name()
+                    ));
+                    continue;
+                }
+
+                let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+                let (Some(positions), Some(joints), Some(weights)) = (
+                    reader.read_positions(),
+                    reader.read_joints(0),
+                    reader.read_weights(0),
+                ) else {
+                    warn(format!(
+                        "skipping unskinned primitive of {:?}",
+                        node_mesh.name()
+                    ));
+                    continue;
+                };
+
+                let offset = mesh.positions.len() as u32;
+                let positions = positions.map(Vec3::from).collect::<Vec<_>>();
+                let joints = joints.into_u16().collect::<Vec<_>>();
+                let weights = weights.into_f32().collect::<Vec<_>>();
+                let uvs = match reader.read_tex_coords(0) {
+                    Some(uvs) => uvs.into_f32().map(Vec2::from).collect(),
+                    None => vec![Vec2::ZERO; positions.len()],
+                };
+                let indices = match reader.read_indices() {
+                    Some(indices) => indices.into_u32().collect::<Vec<_>>(),
+                    None => (0..positions.len() as u32).collect(),
+                };
+                if [joints.len(), weights.len(), uvs.len()] != [positions.len(); 3] {
+                    return Err(Error::Unsupported(format!(
+                        "a primitive of {:?} has {} positions but {} joints, {} weights and {} UVs",
+                        node_mesh.name(),
+                        positions.len(),
+                        joints.len(),
+                        weights.len(),
+                        uvs.len()
+                    )));
+                }
+                if let Some(index) = indices
+                    .iter()
+                    .find(|index| **index as usize >= positions.len())
+                {
+                    return Err(Error::Unsupported(format!(
+                        "a primitive of {:?} uses vertex {} but only has {} vertices",
+                        node_mesh.name(),
+                        index,
+                        positions.len()
+                    )));
+                }
+                mesh.positions.extend(positions);
+                mesh.joints.extend(joints);
+                mesh.weights.extend(weights);
+                mesh.uvs.extend(uvs);
+
+                let pbr = primitive.material().pbr_metallic_roughness();
+                mesh.materials.push(MaterialColor {
+                    factor: pbr.base_color_factor(),
+                    texture: pbr
+                        .base_color_texture()
+                        .map(|info| info.texture().source().index()),
+                });
+                let material = mesh.materials.len() - 1;
+
+                for triangle in indices.chunks_exact(3) {
+                    mesh.triangles.push(Triangle {
+                        indices: [
+                            triangle[0] + offset,
+                            triangle[1] + offset,
+                            triangle[2] + offset,
+                        ],
+                        material,
+                    });
+                }
+            }
+        }
+
+        mesh.global = global.unwrap_or(Mat4::IDENTITY);
+        Ok(mesh)
+    }
+
+    /// Linear blend skinning of every vertex with the skinning matrices of `joint_matrices`,
+    /// placed by the mesh node.
+    pub fn pose(&self, joint_matrices: &[Mat4]) -> Vec<Vec3> {
+        self.positions
+            .iter()
+            .zip(self.joints.iter().zip(&self.weights))
+            .map(|(position, (joints, weights))| {
+                let mut skinned = Vec3::ZERO;
+                let mut total = 0.0;
+                for (joint, weight) in joints.iter().zip(weights) {
+                    if *weight <= 0.0 {
+                        continue;
+                    }
+                    let Some(matrix) = joint_matrices.get(*joint as usize) else {
+                        continue;
+                    };
+                    skinned += matrix.transform_point3(*position) * *weight;
+                    total += *weight;
+                }
+
+                let skinned = if total > 0.0 {
+                    skinned / total
+                } else {
+                    *position
+                };
+                self.global.transform_point3(skinned)
+            })
+            .collect()
+    }
+
+    /// Color of a triangle at the given barycentric coordinates.
+    pub fn color(&self, triangle: &Triangle, barycentric: Vec3, images: &[image::Data]) -> [u8; 4] {
+        let material = self.materials[triangle.material];
+        let mut color = Vec4::from(material.factor);
+
+        if let Some(image) = material.texture.and_then(|texture| images.get(texture)) {
+            let [a, b, c] = triangle.indices.map(|index| self.uvs[index as usize]);
+            let uv = a * barycentric.x + b * barycentric.y + c * barycentric.z;
+            color *= sample_image(image, uv);
+        }
+
+        (color.clamp(Vec4::ZERO, Vec4::ONE) * 255.0)
+            .round()
+            .to_array()
+            .map(|channel| channel as u8)
+    }
+}
+
+/// Nearest texel lookup with repeat wrapping.
+fn sample_image(image: &image::Data, uv: Vec2) -> Vec4 {
+    let channels = match image.format {
+        image::Format::R8 => 1,
+        image::Format::R8G8 => 2,
+        image::Format::R8G8B8 => 3,
+        image::Format::R8G8B8A8 => 4,
+        _ => return Vec4::ONE,
+    };
+
+    let x = ((uv.x.rem_euclid(1.0) * image.width as f32) as u32).min(image.width - 1);
+    let y = ((uv.y.rem_euclid(1.0) * image.height as f32) as u32).min(image.height - 1);
+    let offset = ((y * image.width + x) * channels) as usize;
+    let texel = &image.pixels[offset..offset + channels as usize];
+
+    let channel = |i: usize| texel.get(i).map_or(1.0, |value| *value as f32 / 255.0);
+    match channels {
+        1 => Vec4::new(channel(0), channel(0), channel(0), 1.0),
+        _ => Vec4::new(channel(0), channel(1), channel(2), channel(3)),
+    }
+}
+
+/// Skinning matrix of every joint of the skin, in the order used by `JOINTS_0`.
+///
+/// As in the glTF spec the matrices are relative to the mesh node at `mesh_global`, which
+/// `SkinnedMesh::pose` applies again.
+pub fn joint_matrices(
+    skin: &gltf::Skin,
+    buffers: &[buffer::Data],
+    skeleton: &Skeleton,
+    globals: &[Transform],
+    mesh_global: Mat4,
+) -> Vec<Mat4> {
+    let inverse_mesh_global = mesh_global.inverse();
+    let reader = skin.reader(|buffer| Some(&buffers[buffer.index()]));
+    let inverse_bind_matrices = reader
+        .read_inverse_bind_matrices()
+        .map(|matrices| {
+            matrices
+                .map(|matrix| Mat4::from_cols_array_2d(&matrix))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    skin.joints()
+        .enumerate()
+        .map(|(i, joint)| {
+            let inverse_bind = inverse_bind_matrices
+                .get(i)
+                .copied()
+                .unwrap_or(Mat4::IDENTITY);
+            let global = skeleton
+                .bones
+                .iter()
+                .position(|bone| bone.node == joint.index())
+                .map_or(Mat4::IDENTITY, |bone| globals[bone].compute_matrix());
+            inverse_mesh_global * global * inverse_bind
+        })
+        .collect()
+}
+
+/// Global transform of a node of the document.
+fn node_global(document: &Document, node: usize) -> Mat4 {
+    let mut parents = HashMap::new();
+    for parent in document.nodes() {
+        for child in parent.children() {
+            parents.insert(child.index(), parent.index());
+        }
+    }
+
+    let nodes = document.nodes().collect::<Vec<_>>();
+    let mut global = Mat4::IDENTITY;
+    let mut current = Some(node);
+    while let Some(index) = current {
+        global = Mat4::from_cols_array_2d(&nodes[index].transform().matrix()) * global;
+        current = parents.get(&index).copied();
+    }
+    global
+}
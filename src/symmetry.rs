@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::{
+    coords::Basis,
+    error::warn,
+    layers::Side,
+    rig::{BonePart, Skeleton},
+    tiles::VoxelModel,
+};
+
+/// The name of the same bone on the other side, like `RightArm` for `LeftArm`, `hand.L` for
+/// `hand.R` or `r_foot` for `l_foot`.
+pub fn mirror_name(name: &str) -> Option<String> {
+    for (left, right) in [("Left", "Right"), ("left", "right"), ("LEFT", "RIGHT")] {
+        if name.contains(left) {
+            return Some(name.replacen(left, right, 1));
+        }
+        if name.contains(right) {
+            return Some(name.replacen(right, left, 1));
+        }
+    }
+
+    for separator in ['_', '.', '-', ' ', ':'] {
+        for (from, to) in [('L', 'R'), ('R', 'L'), ('l', 'r'), ('r', 'l')] {
+            if let Some(stem) = name.strip_suffix(&format!("{}{}", separator, from)) {
+                return Some(format!("{}{}{}", stem, separator, to));
+            }
+            if let Some(stem) = name.strip_prefix(&format!("{}{}", from, separator)) {
+                return Some(format!("{}{}{}", to, separator, stem));
+            }
+        }
+    }
+    None
+}
+
+/// The bone on the other side of every bone that has one.
+///
+/// Bones are paired by name first. The rest are paired with a bone at the same depth whose joint
+/// lies where their own joint lands when mirrored across `plane`, within `tolerance`. `positions`
+/// are the rest positions of the joints in magicavoxel axes, where x runs from the right of the
+/// character to its left.
+pub fn find_pairs(
+    skeleton: &Skeleton,
+    positions: &[Vec3],
+    plane: f32,
+    tolerance: f32,
+) -> Vec<Option<usize>> {
+    let mut pairs = vec![None; skeleton.bones.len()];
+
+    let mut by_name = HashMap::new();
+    for (index, bone) in skeleton.bones.iter().enumerate() {
+        for name in bone.names() {
+            by_name.entry(name).or_insert(index);
+        }
+    }
+    for (index, bone) in skeleton.bones.iter().enumerate() {
+        if pairs[index].is_some() {
+            continue;
+        }
+        let other = bone.names().iter().find_map(|name| {
+            let other = *by_name.get(&mirror_name(name)?)?;
+            (other != index && pairs[other].is_none()).then_some(other)
+        });
+        if let Some(other) = other {
+            pairs[index] = Some(other);
+            pairs[other] = Some(index);
+        }
+    }
+
+    let depths = skeleton.bones.iter().fold(vec![], |mut depths, bone| {
+        depths.push(bone.parent.map_or(0, |parent: usize| depths[parent] + 1));
+        depths
+    });
+    for index in 0..skeleton.bones.len() {
+        let position = positions[index];
+        if pairs[index].is_some() || (position.x - plane).abs() <= tolerance {
+            continue;
+        }
+        let mirrored = Vec3::new(2.0 * plane - position.x, position.y, position.z);
+        let other = (0..skeleton.bones.len())
+            .filter(|other| {
+                *other != index && pairs[*other].is_none() && depths[*other] == depths[index]
+            })
+            .map(|other| (other, positions[other].distance(mirrored)))
+            .filter(|(_, distance)| *distance <= tolerance)
+            .min_by(|(_, a), (_, b)| a.total_cmp(b));
+        if let Some((other, _)) = other {
+            pairs[index] = Some(other);
+            pairs[other] = Some(index);
+        }
+    }
+
+    pairs
+}
+
+/// Makes the parts of the character symmetric, keeping the ones on `side`.
+///
+/// Parts on the other side become mirror images of their pair, parts on the mirror plane are
+/// mirrored onto themselves and grow to an even width so they can be centered on it. Every part
+/// is moved so its translation lands on a whole voxel, which keeps the rest pose free of rounding.
+pub fn mirror_parts(
+    basis: Basis,
+    skeleton: &Skeleton,
+    rest_globals: &[Transform],
+    models: &mut [(BonePart, VoxelModel)],
+    side: Side,
+    scale: f32,
+) {
+    let positions = rest_globals
+        .iter()
+        .map(|global| basis.position(global.translation * scale))
+        .collect::<Vec<_>>();
+    let Some(root) = skeleton.bones.iter().position(|bone| bone.parent.is_none()) else {
+        return;
+    };
+    let plane = positions[root].x.round();
+    let (min, max) = positions.iter().fold(
+        (positions[root], positions[root]),
+        |(min, max), position| (min.min(*position), max.max(*position)),
+    );
+    // a voxel, or a small share of the character for detailed rigs
+    let tolerance = ((max - min).max_element() * 0.01).max(0.5);
+
+    let pairs = find_pairs(skeleton, &positions, plane, tolerance);
+    let side_of = |bone: usize| {
+        let x = positions[bone].x;
+        if x > plane {
+            Some(Side::Left)
+        } else if x < plane {
+            Some(Side::Right)
+        } else {
+            Side::of(&skeleton.bones[bone].display_name())
+        }
+    };
+
+    let mut mirrored = vec![];
+    for (bone, pair) in pairs.iter().enumerate() {
+        let (part, model) = &mut models[bone];
+        match pair {
+            Some(pair) if side_of(bone) != Some(side) => {
+                if side_of(*pair) == Some(side) {
+                    mirrored.push((*pair, bone));
+                }
+            }
+            Some(_) => {}
+            None if (positions[bone].x - plane).abs() <= tolerance => {
+                *model = symmetric_model(model);
+                let mut translation = translation(basis.position(part.center), model.size);
+                translation.x = plane;
+                *part = BonePart {
+                    center: basis.position_from_magica(translation),
+                    size: source_size(basis, model.size),
+                };
+                continue;
+            }
+            None => {
+                if Side::of(&skeleton.bones[bone].display_name()).is_some() {
+                    warn(format!(
+                        "{} has no bone on the other side to mirror",
+                        skeleton.bones[bone].display_name()
+                    ));
+                }
+            }
+        }
+        // the rest pose is exact when every translation is a whole voxel
+        part.center =
+            basis.position_from_magica(translation(basis.position(part.center), model.size));
+    }
+
+    if !mirrored.is_empty() {
+        warn(format!(
+            "mirrored bones: {}",
+            mirrored
+                .iter()
+                .map(|(from, to)| format!(
+                    "{} -> {}",
+                    skeleton.bones[*from].display_name(),
+                    skeleton.bones[*to].display_name()
+                ))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+
+    for (from, to) in mirrored {
+        let (part, model) = &models[from];
+        let model = mirror_model(model);
+        let mut translation = basis.position(part.center);
+        // magicavoxel puts the voxel at half the size on the translation, so a model with an odd
+        // width reaches one voxel further to the left of the character than to its right
+        translation.x = 2.0 * plane - translation.x - (model.size.x % 2) as f32;
+        models[to] = (
+            BonePart {
+                center: basis.position_from_magica(translation),
+                size: part.size,
+            },
+            model,
+        );
+    }
+}
+
+/// The whole voxel to put the translation of a model of `size` on, so its voxels are centered
+/// on `center`. Both are in magicavoxel axes.
+fn translation(center: Vec3, size: UVec3) -> Vec3 {
+    (center - size.as_vec3() / 2.0 + (size / 2).as_vec3()).round()
+}
+
+fn source_size(basis: Basis, size: UVec3) -> UVec3 {
+    basis.position_from_magica(size.as_vec3()).abs().as_uvec3()
+}
+
+/// The model flipped along x, from the right of the character to its left.
+fn mirror_model(model: &VoxelModel) -> VoxelModel {
+    let mut voxels = model
+        .voxels
+        .iter()
+        .map(|(position, color_index)| {
+            let x = model.size.x - 1 - position.x;
+            (UVec3::new(x, position.y, position.z), *color_index)
+        })
+        .collect::<Vec<_>>();
+    voxels.sort_by_key(|(position, _)| (position.z, position.y, position.x));
+    VoxelModel {
+        size: model.size,
+        voxels,
+    }
+}
+
+/// The model together with its mirror image, one voxel wider when that makes its width even.
+fn symmetric_model(model: &VoxelModel) -> VoxelModel {
+    let size = UVec3::new(model.size.x + model.size.x % 2, model.size.y, model.size.z);
+    let mut grid = HashMap::new();
+    for (position, color_index) in &model.voxels {
+        grid.entry(*position).or_insert(*color_index);
+    }
+    for (position, color_index) in &model.voxels {
+        let mirrored = UVec3::new(size.x - 1 - position.x, position.y, position.z);
+        grid.entry(mirrored).or_insert(*color_index);
+    }
+
+    let mut voxels = grid.into_iter().collect::<Vec<_>>();
+    voxels.sort_by_key(|(position, _)| (position.z, position.y, position.x));
+    VoxelModel { size, voxels }
+}
@@ -0,0 +1,69 @@
+use bevy::prelude::*;
+
+/// MagicaVoxel stores voxel coordinates in a byte, so no model can be larger than this.
+pub const MAX_MODEL_SIZE: u32 = 256;
+
+/// Voxels in magicavoxel axes that can be larger than a single model.
+#[derive(Debug, Clone, Default)]
+pub struct VoxelModel {
+    pub size: UVec3,
+    pub voxels: Vec<(UVec3, u8)>,
+}
+
+/// A piece of a `VoxelModel` that fits in a single model.
+#[derive(Debug, Clone)]
+pub struct Tile {
+    /// Where the tile goes relative to the translation of the whole model, in magicavoxel axes.
+    pub offset: IVec3,
+    pub model: dot_vox::Model,
+}
+
+impl VoxelModel {
+    /// Cuts the model into a grid of tiles of at most `MAX_MODEL_SIZE` per axis.
+    ///
+    /// A model that fits is a single tile without offset. Every cell of the grid is returned,
+    /// also the empty ones, so the tiles of models with the same size line up.
+    pub fn tiles(&self) -> Vec<Tile> {
+        let cells = (self.size + UVec3::splat(MAX_MODEL_SIZE - 1)) / MAX_MODEL_SIZE;
+        let cell_index =
+            |cell: UVec3| (cell.x + cell.y * cells.x + cell.z * cells.x * cells.y) as usize;
+
+        let mut tiles = Vec::with_capacity((cells.x * cells.y * cells.z) as usize);
+        for z in 0..cells.z {
+            for y in 0..cells.y {
+                for x in 0..cells.x {
+                    let corner = UVec3::new(x, y, z) * MAX_MODEL_SIZE;
+                    let size = (self.size - corner).min(UVec3::splat(MAX_MODEL_SIZE));
+                    // magicavoxel puts the voxel at half the size of a model on its translation
+                    let offset =
+                        corner.as_ivec3() + (size / 2).as_ivec3() - (self.size / 2).as_ivec3();
+
+                    tiles.push(Tile {
+                        offset,
+                        model: dot_vox::Model {
+                            size: dot_vox::Size {
+                                x: size.x,
+                                y: size.y,
+                                z: size.z,
+                            },
+                            voxels: vec![],
+                        },
+                    });
+                }
+            }
+        }
+
+        for (position, color_index) in &self.voxels {
+            let cell = *position / MAX_MODEL_SIZE;
+            let local = *position % MAX_MODEL_SIZE;
+            tiles[cell_index(cell)].model.voxels.push(dot_vox::Voxel {
+                x: local.x as u8,
+                y: local.y as u8,
+                z: local.z as u8,
+                i: *color_index,
+            });
+        }
+
+        tiles
+    }
+}
@@ -0,0 +1,272 @@
+use std::{collections::HashMap, path::Path};
+
+use bevy::prelude::*;
+use gltf::animation::Interpolation;
+use serde_json::json;
+
+use crate::{
+    error::{Error, Result},
+    gltf_writer::{Channel, ChannelValues, GltfWriter},
+    mesh::{MeshSettings, VoxelMesh},
+    rotation::VoxRotation,
+    vox_tree::{TransformFrame, VoxNode},
+    VoxScene,
+};
+
+/// Writes a `.vox` scene as a glTF, the reverse of a conversion.
+///
+/// Every transform becomes a node and a joint of the skin, every model a greedy meshed node
+/// below its transform and the `_f` keyframes a single animation, `fps` keyframes per second.
+pub fn write_gltf(scene: &VoxScene, settings: &MeshSettings, fps: f32, path: &Path) -> Result<()> {
+    let mut export = Export {
+        writer: GltfWriter::default(),
+        scene,
+        settings: *settings,
+        fps,
+        meshes: HashMap::new(),
+        joints: vec![],
+        channels: vec![],
+    };
+    let roots = export.node(scene.root(), Mat4::IDENTITY)?;
+
+    let Export {
+        mut writer,
+        joints,
+        channels,
+        ..
+    } = export;
+    let (joints, inverse_bind_matrices): (Vec<_>, Vec<_>) = joints.into_iter().unzip();
+    writer.skin(&joints, &inverse_bind_matrices);
+    if !channels.is_empty() {
+        writer.animation("vox", &channels);
+    }
+    writer.save(path, &roots)
+}
+
+struct Export<'a> {
+    writer: GltfWriter,
+    scene: &'a VoxScene,
+    settings: MeshSettings,
+    fps: f32,
+    // glTF mesh of every model that was meshed already
+    meshes: HashMap<u32, usize>,
+    // node and inverse bind matrix of every transform
+    joints: Vec<(usize, Mat4)>,
+    channels: Vec<Channel>,
+}
+
+impl Export<'_> {
+    /// Adds `node` and everything below it, returns the glTF nodes to put below the parent.
+    fn node(&mut self, node: &VoxNode, parent: Mat4) -> Result<Vec<usize>> {
+        match node {
+            VoxNode::Transform(transform) => {
+                let mut frames = transform.frames.iter().collect::<Vec<_>>();
+                frames.sort_by_key(|frame| frame.keyframe.unwrap_or(0));
+                let local = frame_transform(
+                    frames
+                        .first()
+                        .copied()
+                        .unwrap_or(&TransformFrame::default()),
+                    &self.settings,
+                );
+                let global = parent * local.compute_matrix();
+
+                let children = self.node(&transform.child, global)?;
+                let mut gltf_node = json!({
+                    "translation": local.translation.to_array(),
+                    "rotation": local.rotation.to_array(),
+                });
+                if local.scale != Vec3::ONE {
+                    gltf_node["scale"] = json!(local.scale.to_array());
+                }
+                if let Some(name) = node.name() {
+                    gltf_node["name"] = json!(name);
+                }
+                if !children.is_empty() {
+                    gltf_node["children"] = json!(children);
+                }
+                let index = self.writer.node(gltf_node);
+                self.joints.push((index, global.inverse()));
+
+                let keyframes = frames
+                    .into_iter()
+                    .filter(|frame| frame.keyframe.is_some())
+                    .collect::<Vec<_>>();
+                if keyframes.len() > 1 {
+                    self.animate(index, &keyframes);
+                }
+                Ok(vec![index])
+            }
+            VoxNode::Group(group) => {
+                let mut nodes = vec![];
+                for child in &group.children {
+                    nodes.extend(self.node(child, parent)?);
+                }
+                Ok(nodes)
+            }
+            VoxNode::Shape(shape) => {
+                let keyframe = |model: &dot_vox::ShapeModel| {
+                    model
+                        .attributes
+                        .get("_f")
+                        .and_then(|keyframe| keyframe.parse::<u32>().ok())
+                        .unwrap_or(0)
+                };
+                let mut models = shape.models.iter().collect::<Vec<_>>();
+                models.sort_by_key(|model| keyframe(model));
+                let times = models
+                    .iter()
+                    .map(|model| keyframe(model) as f32 / self.fps)
+                    .collect::<Vec<_>>();
+
+                let mut nodes = vec![];
+                for (i, model) in models.iter().enumerate() {
+                    let mut gltf_node = json!({});
+                    if let Some(mesh) = self.mesh(model.model_id)? {
+                        gltf_node["mesh"] = json!(mesh);
+                    }
+                    if let Some(name) = node.name() {
+                        gltf_node["name"] = json!(name);
+                    }
+                    let index = self.writer.node(gltf_node);
+
+                    // a shape with a model per keyframe shows one at a time
+                    if models.len() > 1 {
+                        let scales = (0..models.len())
+                            .map(|shown| if shown == i { Vec3::ONE } else { Vec3::ZERO })
+                            .collect();
+                        self.channels.push(Channel {
+                            node: index,
+                            times: times.clone(),
+                            values: ChannelValues::Scale(scales),
+                            interpolation: Interpolation::Step,
+                        });
+                    }
+                    nodes.push(index);
+                }
+                Ok(nodes)
+            }
+        }
+    }
+
+    fn animate(&mut self, node: usize, frames: &[&TransformFrame]) {
+        let times = frames
+            .iter()
+            .map(|frame| frame.keyframe.unwrap_or(0) as f32 / self.fps)
+            .collect::<Vec<_>>();
+        let frames = frames
+            .iter()
+            .map(|frame| frame_transform(frame, &self.settings))
+            .collect::<Vec<_>>();
+
+        self.channels.push(Channel {
+            node,
+            times: times.clone(),
+            values: ChannelValues::Translation(frames.iter().map(|t| t.translation).collect()),
+            interpolation: Interpolation::Linear,
+        });
+        let scales = frames.iter().map(|t| t.scale).collect::<Vec<_>>();
+        if scales.iter().any(|scale| *scale != Vec3::ONE) {
+            self.channels.push(Channel {
+                node,
+                times: times.clone(),
+                values: ChannelValues::Scale(scales),
+                interpolation: Interpolation::Step,
+            });
+        }
+        // rotations are quarter turns, blending between them would show angles the .vox can't
+        self.channels.push(Channel {
+            node,
+            times,
+            values: ChannelValues::Rotation(frames.iter().map(|t| t.rotation).collect()),
+            interpolation: Interpolation::Step,
+        });
+    }
+
+    /// The mesh of a model, `None` for a model without voxels.
+    fn mesh(&mut self, model_id: u32) -> Result<Option<usize>> {
+        if let Some(mesh) = self.meshes.get(&model_id) {
+            return Ok(Some(*mesh));
+        }
+        let model = self
+            .scene
+            .models()
+            .get(model_id as usize)
+            .ok_or_else(|| Error::InvalidVox(format!("model {} doesn't exist", model_id)))?;
+        let Some(mesh) = VoxelMesh::from_model(model, &self.settings) else {
+            return Ok(None);
+        };
+
+        let attributes = json!({
+            "POSITION": self.writer.vec3s(&mesh.positions),
+            "NORMAL": self.writer.vec3s(&mesh.normals),
+            "COLOR_0": self.writer.vec4s(&mesh.vertex_colors(self.scene.palette())),
+        });
+        let indices = self.writer.indices(&mesh.indices);
+        let mesh = self
+            .writer
+            .mesh(Some(&format!("model{}", model_id)), attributes, indices);
+        self.meshes.insert(model_id, mesh);
+        Ok(Some(mesh))
+    }
+}
+
+/// A keyframe as a transform in the axes and units of the source.
+pub fn frame_transform(frame: &TransformFrame, settings: &MeshSettings) -> Transform {
+    let basis = settings.basis;
+    let translation = basis.position_from_magica(frame.translation.as_vec3()) / settings.scale;
+    let rotation = basis.rotation_from_magica(frame.rotation);
+    // a quaternion can't mirror, that is left to a negative scale
+    let (rotation, scale) = match rotation.to_quat() {
+        Some(rotation) => (rotation, Vec3::ONE),
+        None => (
+            (rotation * VoxRotation::MIRROR_X).to_quat().unwrap(),
+            Vec3::new(-1.0, 1.0, 1.0),
+        ),
+    };
+    Transform {
+        translation,
+        rotation,
+        scale,
+    }
+}
+
+/// Every model below `node` placed by the first keyframe of the transforms above it, with the
+/// name of its shape.
+pub fn rest_models<'a>(
+    node: &'a VoxNode,
+    parent: Mat4,
+    settings: &MeshSettings,
+) -> Vec<(Option<&'a str>, u32, Mat4)> {
+    match node {
+        VoxNode::Transform(transform) => {
+            let frame = transform
+                .frames
+                .iter()
+                .min_by_key(|frame| frame.keyframe.unwrap_or(0))
+                .cloned()
+                .unwrap_or_default();
+            let global = parent * frame_transform(&frame, settings).compute_matrix();
+            rest_models(&transform.child, global, settings)
+        }
+        VoxNode::Group(group) => group
+            .children
+            .iter()
+            .flat_map(|child| rest_models(child, parent, settings))
+            .collect(),
+        // a shape with a model per keyframe starts out with the first one
+        VoxNode::Shape(shape) => shape
+            .models
+            .iter()
+            .min_by_key(|model| {
+                model
+                    .attributes
+                    .get("_f")
+                    .and_then(|keyframe| keyframe.parse::<u32>().ok())
+                    .unwrap_or(0)
+            })
+            .map(|model| (node.name(), model.model_id, parent))
+            .into_iter()
+            .collect(),
+    }
+}
@@ -0,0 +1,306 @@
+use bevy::prelude::*;
+use dot_vox::{Dict, SceneNode};
+
+use crate::rotation::VoxRotation;
+
+/// A node of a MagicaVoxel scene graph that owns its children.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VoxNode {
+    Transform(VoxTransform),
+    Group(VoxGroup),
+    Shape(VoxShape),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoxTransform {
+    pub attributes: Dict,
+    pub frames: Vec<TransformFrame>,
+    pub layer_id: u32,
+    pub child: Box<VoxNode>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct VoxGroup {
+    pub attributes: Dict,
+    pub children: Vec<VoxNode>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoxShape {
+    pub attributes: Dict,
+    pub models: Vec<dot_vox::ShapeModel>,
+}
+
+/// A keyframe of a transform, in magicavoxel axes.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TransformFrame {
+    /// `_f`, only set on animated transforms.
+    pub keyframe: Option<u32>,
+    /// `_t`
+    pub translation: IVec3,
+    /// `_r`
+    pub rotation: VoxRotation,
+    /// Every other attribute of the frame.
+    pub attributes: Dict,
+}
+
+impl TransformFrame {
+    fn from_vox(frame: &dot_vox::Frame) -> Result<Self, String> {
+        let mut attributes = frame.attributes.clone();
+
+        let keyframe = match attributes.remove("_f") {
+            Some(value) => Some(
+                value
+                    .parse()
+                    .map_err(|_| format!("invalid keyframe {:?}", value))?,
+            ),
+            None => None,
+        };
+
+        let translation = match attributes.remove("_t") {
+            Some(value) => {
+                let components = value
+                    .split_whitespace()
+                    .map(|component| component.parse::<i32>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|_| format!("invalid translation {:?}", value))?;
+                let [x, y, z] = components[..] else {
+                    return Err(format!("invalid translation {:?}", value));
+                };
+                IVec3::new(x, y, z)
+            }
+            None => IVec3::ZERO,
+        };
+
+        let rotation = match attributes.remove("_r") {
+            Some(value) => {
+                let byte = value
+                    .parse::<u8>()
+                    .map_err(|_| format!("invalid rotation {:?}", value))?;
+                VoxRotation::from_byte(byte).map_err(|error| error.to_string())?
+            }
+            None => VoxRotation::IDENTITY,
+        };
+
+        Ok(Self {
+            keyframe,
+            translation,
+            rotation,
+            attributes,
+        })
+    }
+
+    fn to_vox(&self) -> dot_vox::Frame {
+        let mut attributes = self.attributes.clone();
+        if let Some(keyframe) = self.keyframe {
+            attributes.insert("_f".to_string(), keyframe.to_string());
+        }
+        attributes.insert(
+            "_t".to_string(),
+            format!(
+                "{} {} {}",
+                self.translation.x, self.translation.y, self.translation.z
+            ),
+        );
+        attributes.insert("_r".to_string(), self.rotation.to_byte().to_string());
+        dot_vox::Frame { attributes }
+    }
+}
+
+impl VoxNode {
+    /// Builds the tree below `index` of a flat scene list as read by `dot_vox`, whose shapes may
+    /// only use the first `model_count` models.
+    pub fn from_scenes(
+        scenes: &[SceneNode],
+        index: u32,
+        model_count: usize,
+    ) -> Result<Self, String> {
+        Self::from_scenes_checked(scenes, index, model_count, &mut vec![])
+    }
+
+    fn from_scenes_checked(
+        scenes: &[SceneNode],
+        index: u32,
+        model_count: usize,
+        ancestors: &mut Vec<u32>,
+    ) -> Result<Self, String> {
+        let node = scenes
+            .get(index as usize)
+            .ok_or_else(|| format!("node {} doesn't exist", index))?;
+        if ancestors.contains(&index) {
+            return Err(format!("node {} is its own ancestor", index));
+        }
+
+        ancestors.push(index);
+        let node = match node {
+            SceneNode::Transform {
+                attributes,
+                frames,
+                child,
+                layer_id,
+            } => VoxNode::Transform(VoxTransform {
+                attributes: attributes.clone(),
+                frames: frames
+                    .iter()
+                    .map(TransformFrame::from_vox)
+                    .collect::<Result<_, _>>()
+                    .map_err(|error| format!("node {}: {}", index, error))?,
+                layer_id: *layer_id,
+                child: Box::new(Self::from_scenes_checked(
+                    scenes,
+                    *child,
+                    model_count,
+                    ancestors,
+                )?),
+            }),
+            SceneNode::Group {
+                attributes,
+                children,
+            } => VoxNode::Group(VoxGroup {
+                attributes: attributes.clone(),
+                children: children
+                    .iter()
+                    .map(|child| Self::from_scenes_checked(scenes, *child, model_count, ancestors))
+                    .collect::<Result<_, _>>()?,
+            }),
+            SceneNode::Shape { attributes, models } => {
+                if let Some(model) = models
+                    .iter()
+                    .find(|model| model.model_id as usize >= model_count)
+                {
+                    return Err(format!(
+                        "node {}: model {} doesn't exist",
+                        index, model.model_id
+                    ));
+                }
+                VoxNode::Shape(VoxShape {
+                    attributes: attributes.clone(),
+                    models: models.clone(),
+                })
+            }
+        };
+        ancestors.pop();
+
+        Ok(node)
+    }
+
+    /// Appends the tree to a flat scene list, parents before their children.
+    ///
+    /// Returns the index of this node.
+    pub fn flatten(&self, scenes: &mut Vec<SceneNode>) -> u32 {
+        let index = scenes.len() as u32;
+        match self {
+            VoxNode::Transform(transform) => {
+                scenes.push(SceneNode::Transform {
+                    attributes: transform.attributes.clone(),
+                    frames: transform
+                        .frames
+                        .iter()
+                        .map(TransformFrame::to_vox)
+                        .collect(),
+                    child: 0,
+                    layer_id: transform.layer_id,
+                });
+                let child_index = transform.child.flatten(scenes);
+                if let SceneNode::Transform { child, .. } = &mut scenes[index as usize] {
+                    *child = child_index;
+                }
+            }
+            VoxNode::Group(group) => {
+                scenes.push(SceneNode::Group {
+                    attributes: group.attributes.clone(),
+                    children: vec![],
+                });
+                let child_indices = group
+                    .children
+                    .iter()
+                    .map(|child| child.flatten(scenes))
+                    .collect();
+                if let SceneNode::Group { children, .. } = &mut scenes[index as usize] {
+                    *children = child_indices;
+                }
+            }
+            VoxNode::Shape(shape) => scenes.push(SceneNode::Shape {
+                attributes: shape.attributes.clone(),
+                models: shape.models.clone(),
+            }),
+        }
+        index
+    }
+
+    pub fn attributes(&self) -> &Dict {
+        match self {
+            VoxNode::Transform(transform) => &transform.attributes,
+            VoxNode::Group(group) => &group.attributes,
+            VoxNode::Shape(shape) => &shape.attributes,
+        }
+    }
+
+    pub fn attributes_mut(&mut self) -> &mut Dict {
+        match self {
+            VoxNode::Transform(transform) => &mut transform.attributes,
+            VoxNode::Group(group) => &mut group.attributes,
+            VoxNode::Shape(shape) => &mut shape.attributes,
+        }
+    }
+
+    /// Sets an attribute, e.g. `_hidden` to `1`.
+    pub fn set_attribute(&mut self, key: &str, value: &str) {
+        self.attributes_mut()
+            .insert(key.to_string(), value.to_string());
+    }
+
+    /// Puts this node and every transform below it on a layer.
+    pub fn set_layer(&mut self, layer: u32) {
+        self.walk_mut(&mut |node| {
+            if let VoxNode::Transform(transform) = node {
+                transform.layer_id = layer;
+            }
+        });
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.attributes().get("_name").map(String::as_str)
+    }
+
+    pub fn children(&self) -> Vec<&VoxNode> {
+        match self {
+            VoxNode::Transform(transform) => vec![&transform.child],
+            VoxNode::Group(group) => group.children.iter().collect(),
+            VoxNode::Shape(_) => vec![],
+        }
+    }
+
+    pub fn children_mut(&mut self) -> Vec<&mut VoxNode> {
+        match self {
+            VoxNode::Transform(transform) => vec![&mut transform.child],
+            VoxNode::Group(group) => group.children.iter_mut().collect(),
+            VoxNode::Shape(_) => vec![],
+        }
+    }
+
+    /// Calls `f` on this node and everything below it, parents first.
+    pub fn walk(&self, f: &mut impl FnMut(&VoxNode)) {
+        f(self);
+        for child in self.children() {
+            child.walk(f);
+        }
+    }
+
+    pub fn walk_mut(&mut self, f: &mut impl FnMut(&mut VoxNode)) {
+        f(self);
+        for child in self.children_mut() {
+            child.walk_mut(f);
+        }
+    }
+
+    /// The first node called `name`, parents are searched before their children.
+    pub fn find_mut(&mut self, name: &str) -> Option<&mut VoxNode> {
+        if self.name() == Some(name) {
+            return Some(self);
+        }
+        self.children_mut()
+            .into_iter()
+            .find_map(|child| child.find_mut(name))
+    }
+}
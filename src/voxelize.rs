@@ -0,0 +1,37 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use gltf::image;
+
+use crate::skinning::SkinnedMesh;
+
+/// Colored voxels keyed by their position along the glTF axes.
+pub type VoxelSet = HashMap<IVec3, [u8; 4]>;
+
+/// Voxelizes the surface of the mesh, `positions` are the (posed) vertices in voxel units.
+pub fn voxelize(mesh: &SkinnedMesh, positions: &[Vec3], images: &[image::Data]) -> VoxelSet {
+    let mut voxels = VoxelSet::new();
+
+    for triangle in &mesh.triangles {
+        let [a, b, c] = triangle.indices.map(|index| positions[index as usize]);
+
+        // sample the triangle at least every half voxel so no voxel along the surface is skipped
+        let longest_edge = a.distance(b).max(b.distance(c)).max(c.distance(a));
+        let steps = (longest_edge * 2.0).ceil().max(1.0) as u32;
+
+        for i in 0..=steps {
+            for j in 0..=steps - i {
+                let u = i as f32 / steps as f32;
+                let v = j as f32 / steps as f32;
+                let barycentric = Vec3::new(1.0 - u - v, u, v);
+                let point = a * barycentric.x + b * barycentric.y + c * barycentric.z;
+
+                voxels
+                    .entry(point.floor().as_ivec3())
+                    .or_insert_with(|| mesh.color(triangle, barycentric, images));
+            }
+        }
+    }
+
+    voxels
+}